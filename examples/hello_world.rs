@@ -2,23 +2,27 @@ extern crate glium;
 extern crate glium_text;
 extern crate cgmath;
 
+use std::rc::Rc;
 use glium::Surface;
-use glium::glutin::{ControlFlow, Event, self, WindowEvent};
+use glium::glutin::{self, dpi::LogicalSize};
+use glium::glutin::event::{Event, WindowEvent};
+use glium::glutin::event_loop::{ControlFlow, EventLoop};
 
 fn main() {
-    let mut event_loop = glutin::EventsLoop::new();
-    let window = glutin::WindowBuilder::new().with_dimensions((1024, 768).into());
+    let event_loop = EventLoop::new();
+    let window = glutin::window::WindowBuilder::new().with_inner_size(LogicalSize::new(1024.0, 768.0));
     let context = glutin::ContextBuilder::new();
     let display = glium::Display::new(window, context, &event_loop).unwrap();
     let system = glium_text::TextSystem::new(&display);
 
-    let font = glium_text::FontTexture::new(&display, &include_bytes!("font.ttf")[..], 70).unwrap();
+    // the event loop takes ownership of everything, so the text holds the font with an `Rc`
+    let font = Rc::new(glium_text::FontTexture::new(&display, &include_bytes!("font.ttf")[..], 70).unwrap());
 
-    let text = glium_text::TextDisplay::new(&system, &font, "Hello world!");
+    let text = glium_text::TextDisplay::new(&system, font, "Hello world!");
     let text_width = text.get_width();
     println!("Text width: {:?}", text_width);
 
-    event_loop.run_forever(|event| {
+    event_loop.run(move |event, _, control_flow| {
         let (w, h) = display.get_framebuffer_dimensions();
 
         let matrix:[[f32; 4]; 4] = cgmath::Matrix4::new(
@@ -33,9 +37,9 @@ fn main() {
         glium_text::draw(&text, &system, &mut target, matrix, (1.0, 1.0, 0.0, 1.0));
         target.finish().unwrap();
 
-        match event {
-            Event::WindowEvent {event: WindowEvent::CloseRequested, ..} => ControlFlow::Break,
-            _ => ControlFlow::Continue,
-        }
+        *control_flow = match event {
+            Event::WindowEvent {event: WindowEvent::CloseRequested, ..} => ControlFlow::Exit,
+            _ => ControlFlow::Wait,
+        };
     });
 }
@@ -1,16 +1,17 @@
 extern crate glium;
 extern crate glium_text;
-extern crate cgmath;
 
 use std::path::Path;
 use glium::Surface;
-use glium::glutin::{ControlFlow, Event, self, WindowEvent};
+use glium::glutin::{self, dpi::LogicalSize};
+use glium::glutin::event::{Event, WindowEvent};
+use glium::glutin::event_loop::{ControlFlow, EventLoop};
 
 fn main() {
     use std::fs::File;
 
-    let mut event_loop = glutin::EventsLoop::new();
-    let window = glutin::WindowBuilder::new().with_dimensions((1024, 768).into());
+    let event_loop = EventLoop::new();
+    let window = glutin::window::WindowBuilder::new().with_inner_size(LogicalSize::new(1024.0, 768.0));
     let context = glutin::ContextBuilder::new();
     let display = glium::Display::new(window, context, &event_loop).unwrap();
     let system = glium_text::TextSystem::new(&display);
@@ -18,9 +19,10 @@ fn main() {
     let font = match std::env::args().nth(1) {
         Some(file) => glium_text::FontTexture::new(&display, File::open(&Path::new(&file)).unwrap(), 70),
         None => {
-            match File::open(&Path::new("C:\\Windows\\Fonts\\Arial.ttf")) {
-                Ok(f) => glium_text::FontTexture::new(&display, f, 70),
-                Err(_) => glium_text::FontTexture::new(&display, &include_bytes!("font.ttf")[..], 70),
+            let database = glium_text::system_fonts::FontDatabase::system();
+            match database.query("sans-serif").and_then(|entry| entry.data().ok().map(|d| (d, entry))) {
+                Some((data, entry)) => glium_text::FontTexture::from_bytes(&display, &data, 70, &entry.options()),
+                None => glium_text::FontTexture::new(&display, &include_bytes!("font.ttf")[..], 70),
             }
         }
     }.unwrap();
@@ -29,21 +31,15 @@ fn main() {
 
     println!("Type with your keyboard");
 
-    event_loop.run_forever(|event| {
+    event_loop.run(move |event, _, control_flow| {
         let text = glium_text::TextDisplay::new(&system, &font, &buffer);
 
         let (w, h) = display.get_framebuffer_dimensions();
 
-        let matrix:[[f32; 4]; 4] = cgmath::Matrix4::new(
-            0.1, 0.0, 0.0, 0.0,
-            0.0, 0.1 * (w as f32) / (h as f32), 0.0, 0.0,
-            0.0, 0.0, 1.0, 0.0,
-            -0.9, 0.0, 0.0, 1.0f32,
-        ).into();
-
         let mut target = display.draw();
         target.clear_color(0.0, 0.0, 0.0, 1.0);
-        glium_text::draw(&text, &system, &mut target, matrix, (1.0, 1.0, 0.0, 1.0));
+        glium_text::draw_pixels(&text, &system, &mut target, (w as f32 * 0.05, h as f32 * 0.5),
+                                (1.0, 1.0, 0.0, 1.0));
         target.finish().unwrap();
 
         if let Event::WindowEvent {event, ..} = event {
@@ -51,10 +47,10 @@ fn main() {
                 WindowEvent::ReceivedCharacter('\r') => buffer.clear(),
                 WindowEvent::ReceivedCharacter(c) if c as u32 == 8 => { buffer.pop(); },
                 WindowEvent::ReceivedCharacter(chr) => buffer.push(chr),
-                WindowEvent::CloseRequested => return ControlFlow::Break,
+                WindowEvent::CloseRequested => return *control_flow = ControlFlow::Exit,
                 _ => ()
             }
         }
-        ControlFlow::Continue
+        *control_flow = ControlFlow::Wait;
     });
 }
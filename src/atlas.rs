@@ -0,0 +1,323 @@
+//! Placement of the glyphs in the texture.
+//!
+//! The glyphs are packed with the skyline bottom-left heuristic: the top edge of the glyphs
+//! placed so far is kept as a list of horizontal segments, and each new glyph goes where its
+//! bottom is the highest. Packing the glyphs from the tallest to the shortest leaves little
+//! empty space. When the glyphs don't fit in the largest texture, they are spread over several
+//! textures.
+
+use AtlasOptions;
+
+/// Index of the texture of a glyph, and position of its top-left corner in this texture.
+pub type Position = (usize, u32, u32);
+
+/// Width and height of each texture, with the position of each glyph.
+pub type Packing = (Vec<(u32, u32)>, Vec<Position>);
+
+/// Places the glyphs of the given dimensions in textures. Returns the width and the height of
+/// each texture, with the index of the texture and the position of the top-left corner of each
+/// glyph, or `None` if a glyph is larger than `max_size` pixels.
+///
+/// The glyphs only use several textures if they don't fit in one texture of `max_size`. They
+/// should be sorted by decreasing height. The empty glyphs are placed at `(0, 0)` in the first
+/// texture.
+pub fn pack(sizes: &[(u32, u32)], options: &AtlasOptions, max_size: u32) -> Option<Packing> {
+    let padding = options.padding;
+    let round = |size: u32| if options.power_of_two { ::get_nearest_po2(size) } else { size };
+
+    // we start with the width of a square texture, and make it wider if the glyphs need a
+    // texture that is too high
+    let area: f64 = sizes.iter()
+                         .map(|&(width, height)| (width + padding) as f64 *
+                                                 (height + padding) as f64)
+                         .sum();
+    let widest = sizes.iter().map(|&(width, _)| width).max().unwrap_or(0) + 2 * padding;
+    if widest > max_size {
+        return None;
+    }
+    let mut width = round((area.sqrt() as u32).max(widest).max(1)).min(max_size);
+
+    loop {
+        let max_pages = if width == max_size { ::std::usize::MAX } else { 1 };
+        if let Some((heights, positions)) = pack_with_width(sizes, padding, width, max_size,
+                                                            max_pages)
+        {
+            let pages = heights.into_iter()
+                               .map(|height| (width, round(height.max(1)).min(max_size)))
+                               .collect();
+            return Some((pages, positions));
+        }
+
+        if width == max_size {
+            return None;
+        }
+        width = round(width * 2).min(max_size);
+    }
+}
+
+/// Places the glyphs in at most `max_pages` textures of the given width. Returns the height
+/// that is used in each texture, with the position of each glyph.
+fn pack_with_width(sizes: &[(u32, u32)], padding: u32, width: u32, max_height: u32,
+                   max_pages: usize) -> Option<(Vec<u32>, Vec<Position>)>
+{
+    // the glyphs are placed with their padding on the right and at the bottom, in a skyline
+    // that leaves room for the padding at the left and at the top of the texture
+    if width <= padding || max_height <= padding {
+        return None;
+    }
+    let mut pages = vec![Skyline::new(width - padding, max_height - padding)];
+    let mut positions = Vec::with_capacity(sizes.len());
+
+    for &(glyph_width, glyph_height) in sizes {
+        if glyph_width == 0 || glyph_height == 0 {
+            positions.push((0, 0, 0));
+            continue;
+        }
+
+        // the glyph goes in the first texture where it fits, or in a new one
+        let (width, height) = (glyph_width + padding, glyph_height + padding);
+        let placed = pages.iter_mut().enumerate()
+                          .filter_map(|(page, skyline)| {
+                              skyline.insert(width, height).map(|(x, y)| (page, x, y))
+                          })
+                          .next();
+
+        let (page, x, y) = match placed {
+            Some(position) => position,
+            None if pages.len() < max_pages => {
+                let mut skyline = Skyline::new(pages[0].width, pages[0].max_height);
+                let (x, y) = skyline.insert(width, height)?;
+                pages.push(skyline);
+                (pages.len() - 1, x, y)
+            },
+            None => return None,
+        };
+        positions.push((page, x + padding, y + padding));
+    }
+
+    Some((pages.iter().map(|skyline| skyline.height + padding).collect(), positions))
+}
+
+/// The top edge of the rectangles placed in a texture.
+struct Skyline {
+    width: u32,
+    max_height: u32,
+
+    // horizontal segments as `(x, y, width)`, from left to right and covering the whole width ;
+    // `y` grows downwards
+    segments: Vec<(u32, u32, u32)>,
+
+    // bottom of the lowest rectangle
+    height: u32,
+}
+
+impl Skyline {
+    fn new(width: u32, max_height: u32) -> Skyline {
+        Skyline {
+            width: width,
+            max_height: max_height,
+            segments: vec![(0, 0, width)],
+            height: 0,
+        }
+    }
+
+    /// Places a rectangle as high as possible, and then as much on the left as possible.
+    /// Returns the position of its top-left corner, or `None` if it doesn't fit.
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32)> = None;
+        for index in 0 .. self.segments.len() {
+            if let Some(y) = self.fit(index, width, height) {
+                if best.map_or(true, |(_, best_y)| y < best_y) {
+                    best = Some((index, y));
+                }
+            }
+        }
+
+        let (index, y) = best?;
+        let x = self.segments[index].0;
+        self.segments.insert(index, (x, y + height, width));
+
+        // the segments below the rectangle are removed or shortened
+        let right = x + width;
+        while index + 1 < self.segments.len() {
+            let (next_x, next_y, next_width) = self.segments[index + 1];
+            if next_x >= right {
+                break;
+            }
+            if next_x + next_width <= right {
+                self.segments.remove(index + 1);
+            } else {
+                self.segments[index + 1] = (right, next_y, next_x + next_width - right);
+                break;
+            }
+        }
+
+        // neighbouring segments at the same height are merged
+        let mut merged: Vec<(u32, u32, u32)> = Vec::with_capacity(self.segments.len());
+        for &segment in &self.segments {
+            match merged.last_mut() {
+                Some(last) if last.1 == segment.1 => last.2 += segment.2,
+                _ => merged.push(segment),
+            }
+        }
+        self.segments = merged;
+
+        self.height = self.height.max(y + height);
+        Some((x, y))
+    }
+
+    /// Returns the position of the top of a rectangle whose left edge is at the start of a
+    /// segment, or `None` if it doesn't fit there.
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.segments[index].0;
+        if x + width > self.width {
+            return None;
+        }
+
+        // the rectangle rests on the highest segment below it
+        let mut y = 0;
+        let mut covered = 0;
+        for &(_, segment_y, segment_width) in &self.segments[index ..] {
+            if covered >= width {
+                break;
+            }
+            y = y.max(segment_y);
+            covered += segment_width;
+        }
+
+        if y + height > self.max_height {
+            return None;
+        }
+        Some(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack, pack_with_width, Skyline};
+    use AtlasOptions;
+
+    // checks that the glyphs are in their texture, and separated from each other and from the
+    // edges by the padding
+    fn check_packing(sizes: &[(u32, u32)], padding: u32, pages: &[(u32, u32)],
+                     positions: &[(usize, u32, u32)])
+    {
+        assert_eq!(sizes.len(), positions.len());
+        let glyphs: Vec<_> = sizes.iter().zip(positions)
+                                  .filter(|&(&(width, height), _)| width != 0 && height != 0)
+                                  .collect();
+
+        for &(&(width, height), &(page, x, y)) in &glyphs {
+            assert!(page < pages.len());
+            assert!(x >= padding && x + width + padding <= pages[page].0);
+            assert!(y >= padding && y + height + padding <= pages[page].1);
+        }
+
+        for (index, &(&(width, height), &(page, x, y))) in glyphs.iter().enumerate() {
+            for &(&(other_width, other_height), &(other_page, other_x, other_y)) in
+                &glyphs[index + 1 ..]
+            {
+                assert!(page != other_page ||
+                        x + width + padding <= other_x || other_x + other_width + padding <= x ||
+                        y + height + padding <= other_y || other_y + other_height + padding <= y);
+            }
+        }
+    }
+
+    #[test]
+    fn pack_without_overlaps() {
+        let mut sizes: Vec<_> = (0 .. 100).map(|i| (3 + i * 7 % 23, 2 + i * 11 % 19)).collect();
+        sizes.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let options = AtlasOptions::default();
+        let (pages, positions) = pack(&sizes, &options, 1024).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].0.is_power_of_two() && pages[0].1.is_power_of_two());
+        check_packing(&sizes, options.padding, &pages, &positions);
+
+        let options = AtlasOptions { padding: 0, .. Default::default() };
+        let (pages, positions) = pack(&sizes, &options, 1024).unwrap();
+        check_packing(&sizes, 0, &pages, &positions);
+    }
+
+    #[test]
+    fn pack_exact_dimensions() {
+        let options = AtlasOptions { power_of_two: false, .. Default::default() };
+        assert_eq!(pack(&[(10, 10)], &options, 1024), Some((vec![(14, 14)], vec![(0, 2, 2)])));
+
+        let options = AtlasOptions::default();
+        assert_eq!(pack(&[(10, 10)], &options, 1024), Some((vec![(16, 16)], vec![(0, 2, 2)])));
+    }
+
+    #[test]
+    fn pack_too_wide() {
+        // the glyph and the padding on both of its sides must fit
+        let options = AtlasOptions::default();
+        assert!(pack(&[(61, 4)], &options, 64).is_none());
+        assert!(pack(&[(61, 4), (3, 3)], &options, 64).is_none());
+        assert!(pack(&[(60, 4)], &options, 64).is_some());
+
+        // at most one page
+        assert!(pack_with_width(&[(10, 10), (10, 10)], 2, 14, 14, 1).is_none());
+        assert!(pack_with_width(&[(10, 10)], 2, 2, 14, 1).is_none());
+    }
+
+    #[test]
+    fn pack_empty_glyphs() {
+        let sizes = [(5, 5), (0, 7), (3, 0), (4, 4)];
+        let (pages, positions) = pack(&sizes, &AtlasOptions::default(), 1024).unwrap();
+        assert_eq!(positions[1], (0, 0, 0));
+        assert_eq!(positions[2], (0, 0, 0));
+        check_packing(&sizes, 2, &pages, &positions);
+
+        let (pages, positions) = pack(&[], &AtlasOptions::default(), 1024).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(positions.is_empty());
+    }
+
+    #[test]
+    fn pack_several_pages() {
+        // about 25 glyphs fit in each texture
+        let sizes: Vec<_> = (0 .. 100).map(|i| (10 - i / 50, 10 - i / 30)).collect();
+        let options = AtlasOptions::default();
+        let (pages, positions) = pack(&sizes, &options, 64).unwrap();
+        assert!(pages.len() > 1);
+        assert!(pages.iter().all(|&(width, height)| width == 64 && height <= 64));
+        check_packing(&sizes, options.padding, &pages, &positions);
+
+        // no texture is empty
+        for page in 0 .. pages.len() {
+            assert!(positions.iter().any(|&(glyph_page, _, _)| glyph_page == page));
+        }
+
+        let options = AtlasOptions { power_of_two: false, .. Default::default() };
+        let (pages, positions) = pack(&sizes, &options, 64).unwrap();
+        assert!(pages.len() > 1);
+        check_packing(&sizes, options.padding, &pages, &positions);
+    }
+
+    #[test]
+    fn skyline_insert() {
+        let mut skyline = Skyline::new(10, 100);
+        assert_eq!(skyline.insert(4, 3), Some((0, 0)));
+        assert_eq!(skyline.segments, vec![(0, 3, 4), (4, 0, 6)]);
+
+        // the rectangle goes where it is the highest, and the segments at the same height merge
+        assert_eq!(skyline.insert(6, 3), Some((4, 0)));
+        assert_eq!(skyline.segments, vec![(0, 3, 10)]);
+
+        assert_eq!(skyline.insert(3, 5), Some((0, 3)));
+        assert_eq!(skyline.insert(7, 2), Some((3, 3)));
+        assert_eq!(skyline.segments, vec![(0, 8, 3), (3, 5, 7)]);
+        assert_eq!(skyline.height, 8);
+
+        // a rectangle that covers several segments rests on the highest one
+        assert_eq!(skyline.insert(5, 1), Some((3, 5)));
+        assert_eq!(skyline.insert(10, 1), Some((0, 8)));
+        assert_eq!(skyline.segments, vec![(0, 9, 10)]);
+
+        assert_eq!(skyline.insert(11, 1), None);
+        assert_eq!(skyline.insert(1, 92), None);
+        assert_eq!(skyline.insert(1, 91), Some((0, 9)));
+    }
+}
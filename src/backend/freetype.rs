@@ -0,0 +1,770 @@
+//! Glyph source based on the FreeType library.
+
+use freetype;
+use libc;
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+use super::{bgra_to_rgba, decode_name, resize_coverage, resize_rgba, tag_to_bytes};
+use super::{BitmapFormat, Encoding, GlyphBitmap, GlyphSource, MarkAnchors};
+use {FontMetrics, Hinting, NamedInstance, RasterOptions, SubpixelOrder, VariationAxis};
+#[cfg(feature = "system-fonts")]
+use super::FaceInfo;
+#[cfg(feature = "system-fonts")]
+use std::ffi::CStr;
+
+// the multiple masters API and the accessors of the raw tables aren't in `freetype-sys`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_Var_Axis {
+    name: *mut freetype::FT_String,
+    minimum: freetype::FT_Fixed,
+    def: freetype::FT_Fixed,
+    maximum: freetype::FT_Fixed,
+    tag: freetype::FT_ULong,
+    strid: freetype::FT_UInt,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_Var_Named_Style {
+    coords: *mut freetype::FT_Fixed,
+    strid: freetype::FT_UInt,
+    psid: freetype::FT_UInt,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_MM_Var {
+    num_axis: freetype::FT_UInt,
+    num_designs: freetype::FT_UInt,
+    num_namedstyles: freetype::FT_UInt,
+    axis: *mut FT_Var_Axis,
+    namedstyle: *mut FT_Var_Named_Style,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct FT_SfntName {
+    platform_id: freetype::FT_UShort,
+    encoding_id: freetype::FT_UShort,
+    language_id: freetype::FT_UShort,
+    name_id: freetype::FT_UShort,
+    string: *mut freetype::FT_Byte,
+    string_len: freetype::FT_UInt,
+}
+
+const FT_FACE_FLAG_VERTICAL: freetype::FT_Long = 1 << 5;
+const FT_FACE_FLAG_MULTIPLE_MASTERS: freetype::FT_Long = 1 << 8;
+const TTAG_GPOS: freetype::FT_ULong = 0x47504f53;
+const TTAG_GSUB: freetype::FT_ULong = 0x47535542;
+const TTAG_OS2: freetype::FT_ULong = 0x4f532f32;
+
+extern "C" {
+    // `freetype-sys` declares this function as `FT_Select_CharMap`, which doesn't link
+    fn FT_Select_Charmap(face: freetype::FT_Face, encoding: freetype::FT_Encoding)
+                         -> freetype::FT_Error;
+
+    // older versions of `freetype-sys` don't declare the synthetic styles
+    fn FT_GlyphSlot_Oblique(slot: freetype::FT_GlyphSlot);
+    fn FT_GlyphSlot_Embolden(slot: freetype::FT_GlyphSlot);
+
+    fn FT_Get_MM_Var(face: freetype::FT_Face, amaster: *mut *mut FT_MM_Var) -> freetype::FT_Error;
+    fn FT_Done_MM_Var(library: freetype::FT_Library, amaster: *mut FT_MM_Var) -> freetype::FT_Error;
+    fn FT_Set_Var_Design_Coordinates(face: freetype::FT_Face, num_coords: freetype::FT_UInt,
+                                     coords: *mut freetype::FT_Fixed) -> freetype::FT_Error;
+
+    fn FT_Load_Sfnt_Table(face: freetype::FT_Face, tag: freetype::FT_ULong,
+                          offset: freetype::FT_Long, buffer: *mut freetype::FT_Byte,
+                          length: *mut freetype::FT_ULong) -> freetype::FT_Error;
+
+    fn FT_Get_Sfnt_Name_Count(face: freetype::FT_Face) -> freetype::FT_UInt;
+    fn FT_Get_Sfnt_Name(face: freetype::FT_Face, idx: freetype::FT_UInt,
+                        aname: *mut FT_SfntName) -> freetype::FT_Error;
+}
+
+/// Returns the number of memory blocks currently allocated by a library, whose `user` field
+/// points to it.
+unsafe fn live_blocks<'a>(memory: freetype::FT_Memory) -> &'a Cell<usize> {
+    &*((*memory).user as *const Cell<usize>)
+}
+
+extern "C" fn alloc_library(memory: freetype::FT_Memory, size: libc::c_long) -> *mut libc::c_void {
+    unsafe {
+        let block = libc::malloc(size as libc::size_t);
+        if !block.is_null() {
+            let live_blocks = live_blocks(memory);
+            live_blocks.set(live_blocks.get() + 1);
+        }
+        block
+    }
+}
+
+extern "C" fn free_library(memory: freetype::FT_Memory, block: *mut libc::c_void) {
+    unsafe {
+        if !block.is_null() {
+            let live_blocks = live_blocks(memory);
+            live_blocks.set(live_blocks.get() - 1);
+        }
+        libc::free(block)
+    }
+}
+
+extern "C" fn realloc_library(_memory: freetype::FT_Memory,
+                              _cur_size: libc::c_long,
+                              new_size: libc::c_long,
+                              block: *mut libc::c_void) -> *mut libc::c_void {
+    // FreeType calls `alloc` and `free` instead when the old or new size is 0, so reallocating
+    // never changes the number of live blocks
+    unsafe { libc::realloc(block, new_size as libc::size_t) }
+}
+
+/// An instance of the FreeType library, freed when dropped.
+///
+/// A FreeType library and the faces created from it must not be used from multiple threads at
+/// the same time. The raw pointers inside this struct and `Face` make them neither `Send` nor
+/// `Sync`, so this is enforced by the compiler. Separate libraries can be used concurrently.
+pub struct Library {
+    raw: freetype::FT_Library,
+    // FreeType keeps a pointer to this struct until the library is destroyed, so it is boxed
+    // to give it a stable address
+    _memory: Box<freetype::FT_MemoryRec>,
+    // number of memory blocks currently allocated by this library, pointed to by the `user`
+    // field of `_memory` ; it is shared so that it can be checked once the library is dropped,
+    // and only the tests read it
+    #[cfg_attr(not(test), allow(dead_code))]
+    live_blocks: Rc<Cell<usize>>,
+}
+
+impl Library {
+    /// Builds a new library with the default modules.
+    pub fn new() -> Result<Library, ()> {
+        // taken from https://github.com/PistonDevelopers/freetype-rs/blob/master/src/library.rs
+        let live_blocks = Rc::new(Cell::new(0));
+        let mut memory = Box::new(freetype::FT_MemoryRec {
+            user: &*live_blocks as *const Cell<usize> as *mut libc::c_void,
+            alloc: alloc_library,
+            free: free_library,
+            realloc: realloc_library,
+        });
+
+        unsafe {
+            let mut raw = ptr::null_mut();
+            if freetype::FT_New_Library(&mut *memory, &mut raw) != freetype::FT_Err_Ok {
+                return Err(());
+            }
+            freetype::FT_Add_Default_Modules(raw);
+
+            Ok(Library {
+                raw: raw,
+                _memory: memory,
+                live_blocks: live_blocks,
+            })
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            freetype::FT_Done_Library(self.raw);
+        }
+    }
+}
+
+/// A FreeType face loaded from memory, freed when dropped.
+///
+/// Each face owns its own `Library`, which means that different faces can be used from
+/// different threads.
+pub struct Face<'a> {
+    face: freetype::FT_Face,
+    // encoding of the selected character map
+    encoding: Encoding,
+    // `None` for grayscale antialiasing
+    subpixel: Option<SubpixelOrder>,
+    raster: RasterOptions,
+    // for fonts that only contain bitmaps, like most color emoji fonts, ratio between the
+    // requested size and the size of the selected bitmaps
+    bitmap_scale: f32,
+    // the face must be destroyed before the library ; `Drop::drop` runs before the fields
+    // are dropped
+    library: Library,
+    marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Face<'a> {
+    /// Loads the face with the given index from the data of a font file.
+    pub fn new(font: &'a [u8], face_index: u32) -> Result<Face<'a>, ()> {
+        let library = Library::new()?;
+
+        // building the freetype face object
+        let face: freetype::FT_Face = unsafe {
+            let mut face = ptr::null_mut();
+            let err = freetype::FT_New_Memory_Face(library.raw, font.as_ptr(),
+                                                   font.len() as freetype::FT_Long,
+                                                   face_index as freetype::FT_Long, &mut face);
+            if err == freetype::FT_Err_Ok {
+                face
+            } else {
+                return Err(());
+            }
+        };
+
+        let mut face = Face {
+            face: face,
+            encoding: Encoding::Unicode,
+            subpixel: None,
+            raster: RasterOptions::default(),
+            bitmap_scale: 1.0,
+            library: library,
+            marker: PhantomData,
+        };
+
+        face.encoding = face.select_charmap();
+        Ok(face)
+    }
+
+    /// Selects the character map that we use to list the characters of the font.
+    ///
+    /// Unicode character maps are preferred, and FreeType picks the UCS-4 one if it exists so
+    /// that characters outside of the basic multilingual plane are available. Fonts with only
+    /// another character map, like Big5 or Shift JIS, keep the one selected by FreeType.
+    fn select_charmap(&mut self) -> Encoding {
+        unsafe {
+            if FT_Select_Charmap(self.face, freetype::FT_ENCODING_UNICODE) == freetype::FT_Err_Ok {
+                return Encoding::Unicode;
+            }
+
+            if FT_Select_Charmap(self.face, freetype::FT_ENCODING_MS_SYMBOL) == freetype::FT_Err_Ok {
+                return Encoding::Symbol;
+            }
+
+            if FT_Select_Charmap(self.face, freetype::FT_ENCODING_APPLE_ROMAN) == freetype::FT_Err_Ok {
+                return Encoding::MacRoman;
+            }
+        }
+
+        Encoding::Other
+    }
+
+    /// Returns the content of a table of the font file, or `None` if it doesn't have it.
+    fn sfnt_table(&self, tag: freetype::FT_ULong) -> Option<Vec<u8>> {
+        // the first call returns the length of the table, the second one copies it
+        let mut length = 0;
+        unsafe {
+            if FT_Load_Sfnt_Table(self.face, tag, 0, ptr::null_mut(),
+                                  &mut length) != freetype::FT_Err_Ok
+            {
+                return None;
+            }
+        }
+
+        let mut table = vec![0u8; length as usize];
+        unsafe {
+            if FT_Load_Sfnt_Table(self.face, tag, 0, table.as_mut_ptr(),
+                                  &mut length) != freetype::FT_Err_Ok
+            {
+                return None;
+            }
+        }
+
+        Some(table)
+    }
+
+    /// Calls `callback` with the description of the variation axes, if this is a variable font.
+    fn with_mm_var<F, T>(&self, callback: F) -> Option<T> where F: FnOnce(&FT_MM_Var) -> T {
+        unsafe {
+            if (*self.face).face_flags & FT_FACE_FLAG_MULTIPLE_MASTERS == 0 {
+                return None;
+            }
+
+            let mut mm_var = ptr::null_mut();
+            if FT_Get_MM_Var(self.face, &mut mm_var) != freetype::FT_Err_Ok {
+                return None;
+            }
+
+            let result = callback(&*mm_var);
+            FT_Done_MM_Var(self.library.raw, mm_var);
+            Some(result)
+        }
+    }
+
+    /// Returns an entry of the `name` table, preferably in English.
+    fn name(&self, name_id: u32) -> Option<String> {
+        let mut result = None;
+
+        unsafe {
+            for index in 0 .. FT_Get_Sfnt_Name_Count(self.face) {
+                let mut name = mem::zeroed();
+                if FT_Get_Sfnt_Name(self.face, index, &mut name) != freetype::FT_Err_Ok {
+                    continue;
+                }
+                if name.name_id as u32 != name_id {
+                    continue;
+                }
+
+                let data = slice::from_raw_parts(name.string, name.string_len as usize);
+                if let Some(decoded) = decode_name(name.platform_id, data) {
+                    let english = name.language_id == 0x409 || name.language_id == 0;
+                    if english || result.is_none() {
+                        result = Some(decoded);
+                    }
+                    if english {
+                        break;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl<'a> Drop for Face<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            freetype::FT_Done_Face(self.face);
+        }
+    }
+}
+
+impl<'a> GlyphSource for Face<'a> {
+    fn set_pixel_size(&mut self, font_size: u32) -> Result<(), ()> {
+        unsafe {
+            let face = &*self.face;
+
+            // fonts without outlines can only be loaded at the sizes of their bitmaps, so we
+            // pick the smallest one that is at least as large as requested, or the largest one,
+            // and scale the glyphs afterwards
+            if face.face_flags & freetype::FT_FACE_FLAG_SCALABLE == 0 && face.num_fixed_sizes > 0 {
+                let sizes = slice::from_raw_parts(face.available_sizes,
+                                                  face.num_fixed_sizes as usize);
+                let ppem = |i: usize| sizes[i].y_ppem as f32 / 64.0;
+
+                let mut best = 0;
+                for i in 1 .. sizes.len() {
+                    let better = if ppem(best) < font_size as f32 {
+                        ppem(i) > ppem(best)
+                    } else {
+                        ppem(i) >= font_size as f32 && ppem(i) < ppem(best)
+                    };
+                    if better {
+                        best = i;
+                    }
+                }
+
+                if freetype::FT_Select_Size(self.face, best as freetype::FT_Int) != 0 {
+                    return Err(());
+                }
+                self.bitmap_scale = font_size as f32 / ppem(best);
+                return Ok(());
+            }
+
+            if freetype::FT_Set_Pixel_Sizes(self.face, font_size, font_size) != 0 {
+                return Err(());
+            }
+            self.bitmap_scale = 1.0;
+        }
+
+        Ok(())
+    }
+
+    fn metrics(&self) -> FontMetrics {
+        unsafe {
+            let face = &*self.face;
+            let size = &(*face.size).metrics;
+
+            // the size of an EM and the values, either in font units or in 26.6 pixels for
+            // fonts without outlines
+            let (em, ascender, descender, height) = if face.units_per_EM != 0 {
+                (face.units_per_EM as f32, face.ascender as f32, face.descender as f32,
+                 face.height as f32)
+            } else {
+                (size.y_ppem as f32 * 64.0, size.ascender as f32, size.descender as f32,
+                 size.height as f32)
+            };
+
+            // height of a glyph above the base line, at the current size
+            let glyph_top = |chr: char| {
+                let index = freetype::FT_Get_Char_Index(self.face, chr as freetype::FT_ULong);
+                if index == 0 || size.y_ppem == 0 ||
+                   freetype::FT_Load_Glyph(self.face, index, freetype::FT_LOAD_NO_HINTING) != 0
+                {
+                    return None;
+                }
+                let metrics = &(*face.glyph).metrics;
+                Some(metrics.horiBearingY as f32 / 64.0 / size.y_ppem as f32)
+            };
+
+            // the OS/2 table only has these values since its version 2 ; its bytes are read
+            // because the `TT_OS2` of some versions of `freetype-sys` misses fields
+            let os2 = self.sfnt_table(TTAG_OS2).unwrap_or(Vec::new());
+            let read = |offset: usize| {
+                os2.get(offset .. offset + 2)
+                   .map(|bytes| ((bytes[0] as u16) << 8) | bytes[1] as u16)
+            };
+            let (cap_height, x_height) = match (read(0), read(86), read(88)) {
+                (Some(version), Some(x_height), Some(cap_height))
+                    if version >= 2 && face.units_per_EM != 0 =>
+                {
+                    (cap_height as i16 as f32 / em, x_height as i16 as f32 / em)
+                },
+                _ => (0.0, 0.0)
+            };
+
+            let ascender = ascender / em;
+            let cap_height = if cap_height > 0.0 { Some(cap_height) } else { glyph_top('H') };
+            let cap_height = cap_height.unwrap_or(ascender);
+            let x_height = if x_height > 0.0 { Some(x_height) } else { glyph_top('x') };
+
+            FontMetrics {
+                ascender: ascender,
+                descender: descender / em,
+                line_gap: (height - ascender * em + descender).max(0.0) / em,
+                cap_height: cap_height,
+                x_height: x_height.unwrap_or(cap_height * 2.0 / 3.0),
+                underline_position: face.underline_position as f32 / em,
+                underline_thickness: face.underline_thickness as f32 / em,
+            }
+        }
+    }
+
+    fn characters(&self) -> Vec<(char, u32)> {
+        let mut result = Vec::new();
+        let mut found = HashSet::new();
+
+        unsafe {
+            let mut g: freetype::FT_UInt = 0;
+            let mut c = freetype::FT_Get_First_Char(self.face, &mut g);
+
+            while g != 0 {
+                self.encoding.decode(c as u32, |chr| {
+                    if found.insert(chr) {
+                        result.push((chr, g as u32));
+                    }
+                });
+                c = freetype::FT_Get_Next_Char(self.face, c, &mut g);
+            }
+        }
+
+        result
+    }
+
+    fn set_raster_options(&mut self, options: &RasterOptions, subpixel: Option<SubpixelOrder>) {
+        if subpixel.is_some() {
+            // without a filter the color fringes are very visible ; this fails if FreeType was
+            // built without subpixel rendering, in which case it uses its own method that doesn't
+            // need one
+            unsafe {
+                freetype::FT_Library_SetLcdFilter(self.library.raw,
+                                                  freetype::FT_LCD_FILTER_DEFAULT);
+            }
+        }
+
+        self.subpixel = subpixel;
+        self.raster = *options;
+    }
+
+    fn rasterize(&mut self, glyph: u32, x_offset: f32) -> Option<GlyphBitmap> {
+        unsafe {
+            // the offset is applied to the outline after hinting ; bitmap glyphs ignore it
+            let mut delta = freetype::FT_Vector {
+                x: (x_offset * 64.0).round() as freetype::FT_Pos,
+                y: 0,
+            };
+            freetype::FT_Set_Transform(self.face, ptr::null_mut(), &mut delta);
+
+            let (render_mode, target) = match self.subpixel {
+                _ if !self.raster.antialiasing => {
+                    (freetype::FT_RENDER_MODE_MONO, freetype::FT_LOAD_TARGET_MONO)
+                },
+                None => (freetype::FT_RENDER_MODE_NORMAL, freetype::FT_LOAD_TARGET_NORMAL),
+                Some(order) if order.is_vertical() => {
+                    (freetype::FT_RENDER_MODE_LCD_V, freetype::FT_LOAD_TARGET_LCD_V)
+                },
+                Some(_) => (freetype::FT_RENDER_MODE_LCD, freetype::FT_LOAD_TARGET_LCD),
+            };
+
+            // the hinting algorithm is chosen by the target of `FT_Load_Glyph`, which can be
+            // different from the render mode
+            let hinting = match self.raster.hinting {
+                Hinting::None => freetype::FT_LOAD_NO_HINTING,
+                Hinting::Light => freetype::FT_LOAD_TARGET_LIGHT,
+                Hinting::Normal => target,
+                Hinting::Auto => freetype::FT_LOAD_FORCE_AUTOHINT | target,
+            };
+
+            // the glyph is rendered after the synthetic styles are applied to the outline
+            let flags = freetype::FT_LOAD_COLOR | hinting;
+            if freetype::FT_Load_Glyph(self.face, glyph as freetype::FT_UInt, flags) != 0 {
+                return None;
+            }
+
+            let slot = (*self.face).glyph;
+            if self.raster.oblique {
+                FT_GlyphSlot_Oblique(slot);
+            }
+            if self.raster.embolden {
+                FT_GlyphSlot_Embolden(slot);
+            }
+            if freetype::FT_Render_Glyph(slot, render_mode) != 0 {
+                return None;
+            }
+
+            let slot = &*slot;
+            let bitmap = &slot.bitmap;
+
+            // color glyphs, from bitmaps or from `COLR` layers, are rendered in BGRA
+            let pixel_mode = bitmap.pixel_mode as u32;
+            let (format, bytes_per_pixel) = if pixel_mode == freetype::FT_PIXEL_MODE_BGRA as u32 {
+                (BitmapFormat::Rgba, 4)
+            } else if pixel_mode == freetype::FT_PIXEL_MODE_LCD as u32 ||
+                      pixel_mode == freetype::FT_PIXEL_MODE_LCD_V as u32
+            {
+                // the width or the height is three times larger, with one column or row for
+                // each subpixel
+                (BitmapFormat::Lcd, 1)
+            } else {
+                (BitmapFormat::Coverage, 1)
+            };
+
+            // copying the rows one by one, as they can be padded
+            let row_len = bitmap.width as usize * bytes_per_pixel;
+            let mut buffer = Vec::with_capacity(row_len * bitmap.rows as usize);
+            for y in 0 .. bitmap.rows {
+                let row = bitmap.buffer.offset((y * bitmap.pitch) as isize);
+
+                if pixel_mode == freetype::FT_PIXEL_MODE_MONO as u32 {
+                    // one bit per pixel, the most significant bit first
+                    let row = slice::from_raw_parts(row, (row_len + 7) / 8);
+                    buffer.extend((0 .. row_len).map(|x| {
+                        if row[x / 8] & (0x80 >> (x % 8)) != 0 { 255 } else { 0 }
+                    }));
+                } else {
+                    buffer.extend_from_slice(slice::from_raw_parts(row, row_len));
+                }
+            }
+
+            let mut result = GlyphBitmap {
+                width: bitmap.width as u32,
+                rows: bitmap.rows as u32,
+                left: slot.bitmap_left,
+                top: slot.bitmap_top,
+                advance: slot.advance.x as f32 / 64.0,
+                format: format,
+                buffer: buffer,
+            };
+
+            if pixel_mode == freetype::FT_PIXEL_MODE_LCD as u32 {
+                result.width /= 3;
+            } else if pixel_mode == freetype::FT_PIXEL_MODE_LCD_V as u32 {
+                // interleaving the three rows of each pixel
+                let rows = result.rows / 3;
+                let width = result.width as usize;
+                let mut buffer = Vec::with_capacity(width * rows as usize * 3);
+                for y in 0 .. rows as usize {
+                    for x in 0 .. width {
+                        for subpixel in 0 .. 3 {
+                            buffer.push(result.buffer[(y * 3 + subpixel) * width + x]);
+                        }
+                    }
+                }
+                result.rows = rows;
+                result.buffer = buffer;
+            }
+
+            if format == BitmapFormat::Rgba {
+                bgra_to_rgba(&mut result.buffer);
+            }
+
+            // the bitmaps of fonts without outlines are at the size of the selected strike
+            if self.bitmap_scale != 1.0 {
+                let scale = self.bitmap_scale;
+                let width = ((result.width as f32 * scale).round() as u32).max(1);
+                let rows = ((result.rows as f32 * scale).round() as u32).max(1);
+                result.buffer = if format == BitmapFormat::Rgba {
+                    resize_rgba(&result.buffer, result.width, result.rows, width, rows)
+                } else {
+                    resize_coverage(&result.buffer, result.width, result.rows, width, rows)
+                };
+                result.width = width;
+                result.rows = rows;
+                result.left = (result.left as f32 * scale).round() as i32;
+                result.top = (result.top as f32 * scale).round() as i32;
+                result.advance *= scale;
+            }
+
+            Some(result)
+        }
+    }
+
+    fn variation_axes(&self) -> Vec<VariationAxis> {
+        self.with_mm_var(|mm_var| unsafe {
+            let axes = slice::from_raw_parts(mm_var.axis, mm_var.num_axis as usize);
+            axes.iter().map(|axis| {
+                let tag = tag_to_bytes(axis.tag as u32);
+                VariationAxis {
+                    tag: tag,
+                    name: self.name(axis.strid as u32)
+                              .unwrap_or_else(|| String::from_utf8_lossy(&tag).into_owned()),
+                    min: axis.minimum as f32 / 65536.0,
+                    default: axis.def as f32 / 65536.0,
+                    max: axis.maximum as f32 / 65536.0,
+                }
+            }).collect()
+        }).unwrap_or_else(Vec::new)
+    }
+
+    fn named_instances(&self) -> Vec<NamedInstance> {
+        self.with_mm_var(|mm_var| unsafe {
+            let styles = slice::from_raw_parts(mm_var.namedstyle,
+                                               mm_var.num_namedstyles as usize);
+            styles.iter().map(|style| {
+                let coords = slice::from_raw_parts(style.coords, mm_var.num_axis as usize);
+                NamedInstance {
+                    name: self.name(style.strid as u32).unwrap_or_default(),
+                    coordinates: coords.iter().map(|&c| c as f32 / 65536.0).collect(),
+                }
+            }).collect()
+        }).unwrap_or_else(Vec::new)
+    }
+
+    fn set_variations(&mut self, coordinates: &[f32]) -> Result<(), ()> {
+        let mut coordinates: Vec<freetype::FT_Fixed> =
+            coordinates.iter().map(|&c| (c * 65536.0).round() as freetype::FT_Fixed).collect();
+
+        unsafe {
+            if FT_Set_Var_Design_Coordinates(self.face, coordinates.len() as freetype::FT_UInt,
+                                             coordinates.as_mut_ptr()) != freetype::FT_Err_Ok
+            {
+                return Err(());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mark_anchors(&self, glyphs: &[u32]) -> MarkAnchors {
+        let units_per_em = unsafe { (*self.face).units_per_EM };
+        match self.sfnt_table(TTAG_GPOS) {
+            Some(ref gpos) if units_per_em != 0 => {
+                super::opentype::mark_anchors(gpos, units_per_em, glyphs)
+            },
+            _ => MarkAnchors::default()
+        }
+    }
+
+    fn substitutions(&self, feature: &[u8; 4], glyphs: &[u32]) -> Vec<(u32, u32)> {
+        match self.sfnt_table(TTAG_GSUB) {
+            Some(gsub) => super::opentype::single_substitutions(&gsub, feature, glyphs),
+            None => Vec::new()
+        }
+    }
+
+    fn vertical_metrics(&self, glyph: u32) -> Option<(f32, f32)> {
+        unsafe {
+            let face = &*self.face;
+            if face.face_flags & FT_FACE_FLAG_VERTICAL == 0 || face.units_per_EM == 0 {
+                return None;
+            }
+
+            // without scaling, the metrics are in font units
+            if freetype::FT_Load_Glyph(self.face, glyph, freetype::FT_LOAD_NO_SCALE) != 0 {
+                return None;
+            }
+
+            let metrics = &(*face.glyph).metrics;
+            let em = face.units_per_EM as f32;
+            Some((metrics.vertAdvance as f32 / em,
+                  (metrics.vertBearingY + metrics.horiBearingY) as f32 / em))
+        }
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn faces_count(&self) -> u32 {
+        unsafe { (*self.face).num_faces as u32 }
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn info(&self) -> FaceInfo {
+        unsafe {
+            let to_string = |s: *const libc::c_char| if s.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(s).to_string_lossy().into_owned()
+            };
+
+            let style_flags = (*self.face).style_flags;
+            let os2 = freetype::FT_Get_Sfnt_Table(self.face, freetype::ft_sfnt_os2)
+                                                  as *const freetype::TT_OS2;
+
+            let (weight, width) = if os2.is_null() {
+                let bold = style_flags & freetype::FT_STYLE_FLAG_BOLD != 0;
+                (if bold { 700 } else { 400 }, 5)
+            } else {
+                ((*os2).usWeightClass as u16, (*os2).usWidthClass as u16)
+            };
+
+            FaceInfo {
+                family: to_string((*self.face).family_name),
+                style: to_string((*self.face).style_name),
+                weight: weight,
+                width: width,
+                italic: style_flags & freetype::FT_STYLE_FLAG_ITALIC != 0,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Face;
+    use backend::GlyphSource;
+
+    #[test]
+    fn no_leak() {
+        let font = include_bytes!("../../examples/font.ttf");
+
+        let parameters = ::RasterParameters {
+            subpixel: None,
+            raster: Default::default(),
+            subpixel_positions: 1,
+            atlas: Default::default(),
+        };
+
+        // every block allocated by FreeType must have been freed once the face is dropped
+        for _ in 0 .. 2000 {
+            let mut face = Face::new(&font[..], 0).unwrap();
+            let live_blocks = face.library.live_blocks.clone();
+            let characters = face.characters();
+            ::build_font_image(&mut face, characters, Vec::new(), &[16], &parameters).unwrap();
+            assert!(live_blocks.get() > 0);
+            drop(face);
+            assert_eq!(live_blocks.get(), 0);
+        }
+    }
+
+    #[test]
+    fn synthetic_styles() {
+        let font = include_bytes!("../../examples/font.ttf");
+        let mut face = Face::new(&font[..], 0).unwrap();
+        face.set_pixel_size(32).unwrap();
+        let glyph = face.characters().into_iter().find(|&(chr, _)| chr == 'l').unwrap().1;
+
+        let mut width = |options: ::RasterOptions| {
+            face.set_raster_options(&options, None);
+            face.rasterize(glyph, 0.0).unwrap().width
+        };
+        let regular = width(Default::default());
+
+        // the bold glyph is thicker, and the slanted one leans to the right
+        assert!(width(::RasterOptions { embolden: true, .. Default::default() }) > regular);
+        assert!(width(::RasterOptions { oblique: true, .. Default::default() }) > regular);
+    }
+}
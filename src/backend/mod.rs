@@ -0,0 +1,421 @@
+//! Glyph sources used to fill the font textures.
+//!
+//! Exactly one backend is compiled in, depending on the cargo features. `build_font_image`
+//! only ever talks to the `GlyphSource` trait, so it doesn't care which one produced the
+//! bitmaps and metrics.
+
+#[cfg(not(any(feature = "freetype", feature = "pure-rust")))]
+compile_error!("either the `freetype` or the `pure-rust` feature must be enabled");
+
+#[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
+mod freetype;
+#[cfg(feature = "pure-rust")]
+mod ttf;
+mod opentype;
+
+#[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
+pub use self::freetype::Face;
+#[cfg(feature = "pure-rust")]
+pub use self::ttf::Face;
+
+use {FontMetrics, NamedInstance, RasterOptions, SubpixelOrder, VariationAxis};
+
+/// A font face that can list its characters and rasterize its glyphs.
+pub trait GlyphSource {
+    /// Sets the number of pixels per EM used by the next calls to `rasterize`.
+    fn set_pixel_size(&mut self, font_size: u32) -> Result<(), ()>;
+
+    /// Returns the metrics of the face, in EMs. Must be called after `set_pixel_size`, as fonts
+    /// without outlines only have metrics for their bitmaps.
+    fn metrics(&self) -> FontMetrics;
+
+    /// Returns the list of characters of the font, with the index of their glyph.
+    fn characters(&self) -> Vec<(char, u32)>;
+
+    /// Sets the options used by the next calls to `rasterize`. `subpixel` chooses between
+    /// grayscale and subpixel antialiasing, and is `None` if `options.antialiasing` is false.
+    ///
+    /// The gamma and the contrast are applied by the caller.
+    fn set_raster_options(&mut self, options: &RasterOptions, subpixel: Option<SubpixelOrder>);
+
+    /// Renders a glyph, with its origin moved to the right by `x_offset` pixels, between `0.0`
+    /// and `1.0`. Returns `None` if the glyph couldn't be loaded.
+    fn rasterize(&mut self, glyph: u32, x_offset: f32) -> Option<GlyphBitmap>;
+
+    /// Returns the variation axes of the face. Empty if it isn't a variable font.
+    fn variation_axes(&self) -> Vec<VariationAxis>;
+
+    /// Returns the named instances of the face. Empty if it isn't a variable font.
+    fn named_instances(&self) -> Vec<NamedInstance>;
+
+    /// Sets the value of each variation axis, in the order of `variation_axes`.
+    fn set_variations(&mut self, coordinates: &[f32]) -> Result<(), ()>;
+
+    /// Returns the anchors of the given glyphs used to attach marks to bases. Empty if the face
+    /// has no `GPOS` table.
+    fn mark_anchors(&self, glyphs: &[u32]) -> MarkAnchors;
+
+    /// Returns the glyphs that replace the given glyphs when a feature of the `GSUB` table is
+    /// enabled, like `vert` for the vertical alternates. Only the single substitutions are used.
+    fn substitutions(&self, feature: &[u8; 4], glyphs: &[u32]) -> Vec<(u32, u32)>;
+
+    /// Returns the vertical advance of a glyph and the distance between its vertical origin
+    /// and its horizontal base line, in EMs. `None` if the face has no vertical metrics.
+    fn vertical_metrics(&self, glyph: u32) -> Option<(f32, f32)>;
+
+    /// Returns the number of faces in the file this face was loaded from.
+    #[cfg(feature = "system-fonts")]
+    fn faces_count(&self) -> u32;
+
+    /// Returns the names and style of the face.
+    #[cfg(feature = "system-fonts")]
+    fn info(&self) -> FaceInfo;
+}
+
+/// Names and style of a face, as reported by the font.
+#[cfg(feature = "system-fonts")]
+pub struct FaceInfo {
+    pub family: String,
+    pub style: String,
+
+    // weight between 1 and 1000, where 400 is regular and 700 is bold
+    pub weight: u16,
+
+    // width between 1 and 9, where 5 is normal
+    pub width: u16,
+
+    pub italic: bool,
+}
+
+/// Mark-to-base attachments of a face.
+///
+/// A mark is positioned on a base by making their anchors of the same class coincide. The
+/// classes of the different subtables of the font are numbered one after the other, so that
+/// they never collide.
+#[derive(Default)]
+pub struct MarkAnchors {
+    // glyph of a mark, its class and its anchor, in EMs
+    pub marks: Vec<(u32, u32, (f32, f32))>,
+
+    // glyph of a base, a class of marks that attach to it and the anchor, in EMs
+    pub bases: Vec<(u32, u32, (f32, f32))>,
+}
+
+/// A glyph rendered by a `GlyphSource`.
+///
+/// All the values are in pixels. The layout of the bitmap matches what FreeType produces.
+pub struct GlyphBitmap {
+    // dimensions of the bitmap
+    pub width: u32,
+    pub rows: u32,
+
+    // distance between the origin of the glyph and the left column of the bitmap
+    pub left: i32,
+
+    // distance between the base line and the top row of the bitmap
+    pub top: i32,
+
+    // distance between the origin of this glyph and the origin of the next one
+    pub advance: f32,
+
+    // the pixels, row by row, top row first
+    pub format: BitmapFormat,
+    pub buffer: Vec<u8>,
+}
+
+/// Content of the buffer of a `GlyphBitmap`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitmapFormat {
+    /// One byte per pixel, with the coverage between 0 and 255.
+    Coverage,
+    /// Four bytes per pixel, RGBA with non-premultiplied alpha. Used by color glyphs.
+    Rgba,
+    /// Three bytes per pixel, with the coverage of each subpixel from left to right, or from
+    /// top to bottom for vertical subpixel orders.
+    Lcd,
+}
+
+/// Converts a premultiplied BGRA image to a non-premultiplied RGBA one, in place.
+pub fn bgra_to_rgba(buffer: &mut [u8]) {
+    for pixel in buffer.chunks_mut(4) {
+        let (b, g, r, a) = (pixel[0], pixel[1], pixel[2], pixel[3]);
+        let unmultiply = |c: u8| if a == 0 { 0 } else { (c as u32 * 255 / a as u32).min(255) as u8 };
+        pixel[0] = unmultiply(r);
+        pixel[1] = unmultiply(g);
+        pixel[2] = unmultiply(b);
+        pixel[3] = a;
+    }
+}
+
+/// Resizes a non-premultiplied RGBA image by averaging the pixels.
+///
+/// Color glyphs are usually stored in the font at a single large size, so they have to be
+/// scaled down to the requested size.
+pub fn resize_rgba(buffer: &[u8], width: u32, rows: u32, new_width: u32, new_rows: u32) -> Vec<u8> {
+    let mut result = vec![0u8; (new_width * new_rows * 4) as usize];
+    if width == 0 || rows == 0 {
+        return result;
+    }
+
+    for y in 0 .. new_rows {
+        let y0 = y * rows / new_rows;
+        let y1 = ((y + 1) * rows / new_rows).max(y0 + 1).min(rows);
+
+        for x in 0 .. new_width {
+            let x0 = x * width / new_width;
+            let x1 = ((x + 1) * width / new_width).max(x0 + 1).min(width);
+
+            // averaging with premultiplied alpha, so that transparent pixels don't bleed
+            let mut sum = [0u32; 4];
+            for sy in y0 .. y1 {
+                for sx in x0 .. x1 {
+                    let pixel = &buffer[((sy * width + sx) * 4) as usize ..];
+                    let a = pixel[3] as u32;
+                    sum[0] += pixel[0] as u32 * a;
+                    sum[1] += pixel[1] as u32 * a;
+                    sum[2] += pixel[2] as u32 * a;
+                    sum[3] += a;
+                }
+            }
+
+            let count = (x1 - x0) * (y1 - y0);
+            let destination = &mut result[((y * new_width + x) * 4) as usize ..];
+            if sum[3] != 0 {
+                destination[0] = (sum[0] / sum[3]) as u8;
+                destination[1] = (sum[1] / sum[3]) as u8;
+                destination[2] = (sum[2] / sum[3]) as u8;
+            }
+            destination[3] = (sum[3] / count) as u8;
+        }
+    }
+
+    result
+}
+
+/// Resizes a coverage image by averaging the pixels.
+///
+/// Fonts that only contain bitmaps are scaled like the color glyphs when they don't have the
+/// requested size.
+#[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
+pub fn resize_coverage(buffer: &[u8], width: u32, rows: u32, new_width: u32, new_rows: u32)
+                       -> Vec<u8>
+{
+    let mut result = vec![0u8; (new_width * new_rows) as usize];
+    if width == 0 || rows == 0 {
+        return result;
+    }
+
+    for y in 0 .. new_rows {
+        let y0 = y * rows / new_rows;
+        let y1 = ((y + 1) * rows / new_rows).max(y0 + 1).min(rows);
+
+        for x in 0 .. new_width {
+            let x0 = x * width / new_width;
+            let x1 = ((x + 1) * width / new_width).max(x0 + 1).min(width);
+
+            let mut sum = 0u32;
+            for sy in y0 .. y1 {
+                for sx in x0 .. x1 {
+                    sum += buffer[(sy * width + sx) as usize] as u32;
+                }
+            }
+
+            let count = (x1 - x0) * (y1 - y0);
+            result[(y * new_width + x) as usize] = (sum / count) as u8;
+        }
+    }
+
+    result
+}
+
+/// Encodings of character maps that can be converted to Unicode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// The character codes are Unicode code points.
+    Unicode,
+    /// Microsoft Symbol encoding, used by symbol fonts like Wingdings.
+    Symbol,
+    /// Apple Roman, found in old Macintosh fonts.
+    MacRoman,
+    /// Another encoding, like Big5 or Shift JIS. Only the ASCII codes are decoded, and the
+    /// other codes are skipped.
+    Other,
+}
+
+impl Encoding {
+    /// Calls `callback` with each character that a character code of this encoding stands for.
+    ///
+    /// Codes that are not valid Unicode scalar values, like surrogates, are ignored.
+    pub fn decode<F>(&self, code: u32, mut callback: F) where F: FnMut(char) {
+        match *self {
+            Encoding::Unicode => {
+                if let Some(chr) = ::std::char::from_u32(code) {
+                    callback(chr);
+                }
+            },
+
+            Encoding::Symbol => {
+                // symbol fonts usually put their glyphs in the private use area at
+                // `U+F020-U+F0FF`, but they are meant to be typed with the `U+0020-U+00FF` codes
+                if let Some(chr) = ::std::char::from_u32(code) {
+                    callback(chr);
+                }
+                if (0xf020 ..= 0xf0ff).contains(&code) {
+                    callback((code - 0xf000) as u8 as char);
+                }
+            },
+
+            Encoding::MacRoman => {
+                if code < 0x80 {
+                    callback(code as u8 as char);
+                } else if code <= 0xff {
+                    callback(MAC_ROMAN[(code - 0x80) as usize]);
+                }
+            },
+
+            Encoding::Other => {
+                if code < 0x80 {
+                    callback(code as u8 as char);
+                }
+            },
+        }
+    }
+}
+
+/// Converts an OpenType tag to its four characters.
+pub fn tag_to_bytes(tag: u32) -> [u8; 4] {
+    [(tag >> 24) as u8, (tag >> 16) as u8, (tag >> 8) as u8, tag as u8]
+}
+
+/// Decodes a string of the `name` table.
+///
+/// The Unicode and Windows platforms store the names in UTF-16BE, the Macintosh platform in
+/// Apple Roman.
+pub fn decode_name(platform_id: u16, data: &[u8]) -> Option<String> {
+    match platform_id {
+        0 | 3 => {
+            let units: Vec<u16> = data.chunks(2)
+                                      .filter(|c| c.len() == 2)
+                                      .map(|c| ((c[0] as u16) << 8) | c[1] as u16)
+                                      .collect();
+            String::from_utf16(&units).ok()
+        },
+        1 => {
+            let mut result = String::new();
+            for &b in data {
+                Encoding::MacRoman.decode(b as u32, |c| result.push(c));
+            }
+            Some(result)
+        },
+        _ => None
+    }
+}
+
+// characters of the upper half of the Apple Roman encoding
+const MAC_ROMAN: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è',
+    'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü',
+    '†', '°', '¢', '£', '§', '•', '¶', 'ß', '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø',
+    '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø',
+    '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…', '\u{a0}', 'À', 'Ã', 'Õ', 'Œ', 'œ',
+    '–', '—', '“', '”', '‘', '’', '÷', '◊', 'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ',
+    '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô',
+    '\u{f8ff}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, bgra_to_rgba, resize_rgba};
+
+    fn decode(encoding: Encoding, code: u32) -> Vec<char> {
+        let mut result = Vec::new();
+        encoding.decode(code, |chr| result.push(chr));
+        result
+    }
+
+    #[test]
+    fn decode_unicode() {
+        assert_eq!(decode(Encoding::Unicode, 0x41), vec!['A']);
+        assert_eq!(decode(Encoding::Unicode, 0x1f600), vec!['\u{1f600}']);
+        assert_eq!(decode(Encoding::Unicode, 0xd800), vec![]);
+    }
+
+    #[test]
+    fn decode_symbol() {
+        assert_eq!(decode(Encoding::Symbol, 0xf041), vec!['\u{f041}', 'A']);
+        assert_eq!(decode(Encoding::Symbol, 0xf0ff), vec!['\u{f0ff}', '\u{ff}']);
+        assert_eq!(decode(Encoding::Symbol, 0xf01f), vec!['\u{f01f}']);
+    }
+
+    #[test]
+    fn decode_mac_roman() {
+        assert_eq!(decode(Encoding::MacRoman, 0x41), vec!['A']);
+        assert_eq!(decode(Encoding::MacRoman, 0x80), vec!['Ä']);
+        assert_eq!(decode(Encoding::MacRoman, 0xa5), vec!['•']);
+        assert_eq!(decode(Encoding::MacRoman, 0xca), vec!['\u{a0}']);
+        assert_eq!(decode(Encoding::MacRoman, 0xdb), vec!['€']);
+        assert_eq!(decode(Encoding::MacRoman, 0xf0), vec!['\u{f8ff}']);
+        assert_eq!(decode(Encoding::MacRoman, 0xff), vec!['ˇ']);
+        assert_eq!(decode(Encoding::MacRoman, 0x100), vec![]);
+    }
+
+    #[test]
+    fn decode_other() {
+        assert_eq!(decode(Encoding::Other, 0x41), vec!['A']);
+        assert_eq!(decode(Encoding::Other, 0xa440), vec![]);
+        assert_eq!(decode(Encoding::Other, 0x82a0), vec![]);
+    }
+
+    #[test]
+    fn bgra_swizzled_and_unmultiplied() {
+        let mut buffer = [1, 2, 3, 255,
+                          0x20, 0x40, 0x80, 0x80,
+                          10, 20, 30, 0];
+        bgra_to_rgba(&mut buffer);
+        assert_eq!(buffer, [3, 2, 1, 255,
+                            255, 127, 63, 0x80,
+                            0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn resize_rgba_averages() {
+        let buffer = [255, 0, 0, 255,   255, 0, 0, 255,
+                      0, 0, 255, 255,   0, 0, 255, 255];
+        assert_eq!(resize_rgba(&buffer, 2, 2, 1, 1), vec![127, 0, 127, 255]);
+        assert_eq!(resize_rgba(&buffer, 2, 2, 2, 1), vec![127, 0, 127, 255,   127, 0, 127, 255]);
+
+        // the color of the transparent pixels doesn't bleed
+        let buffer = [255, 0, 0, 255,   0, 255, 0, 0];
+        assert_eq!(resize_rgba(&buffer, 2, 1, 1, 1), vec![255, 0, 0, 127]);
+
+        assert_eq!(resize_rgba(&[1, 2, 3, 4], 1, 1, 2, 1), vec![1, 2, 3, 4,   1, 2, 3, 4]);
+        assert_eq!(resize_rgba(&[], 0, 0, 1, 1), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
+    fn resize_coverage_averages() {
+        use super::resize_coverage;
+
+        let buffer = [255, 255, 0, 0,
+                      255, 255, 0, 0,
+                      0, 0, 0, 255,
+                      0, 0, 255, 255];
+        assert_eq!(resize_coverage(&buffer, 4, 4, 2, 2), vec![255, 0, 0, 191]);
+        assert_eq!(resize_coverage(&[0, 255], 2, 1, 4, 1), vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn face_metrics() {
+        use super::{Face, GlyphSource};
+
+        let font = include_bytes!("../../examples/font.ttf");
+        let mut face = Face::new(&font[..], 0).unwrap();
+        face.set_pixel_size(32).unwrap();
+
+        let metrics = face.metrics();
+        assert!(metrics.x_height > 0.0);
+        assert!(metrics.x_height < metrics.cap_height);
+        assert!(metrics.cap_height <= metrics.ascender);
+    }
+}
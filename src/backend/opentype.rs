@@ -0,0 +1,475 @@
+//! Reading of the `GPOS` and `GSUB` tables.
+//!
+//! Both backends hand the raw tables to this module: FreeType doesn't parse them, and
+//! `ttf-parser` doesn't tell null anchors from real ones.
+
+use super::MarkAnchors;
+
+// lookup types of the `GPOS` table
+const MARK_TO_BASE: u16 = 4;
+const EXTENSION: u16 = 9;
+
+// lookup types of the `GSUB` table
+const SINGLE_SUBSTITUTION: u16 = 1;
+const SUBSTITUTION_EXTENSION: u16 = 7;
+
+/// Reads the mark-to-base anchors of the given glyphs from the content of a `GPOS` table.
+///
+/// Malformed subtables are skipped.
+pub fn mark_anchors(gpos: &[u8], units_per_em: u16, glyphs: &[u32]) -> MarkAnchors {
+    let mut anchors = MarkAnchors::default();
+    let units_per_em = units_per_em as f32;
+
+    let lookup_list = match read_u16(gpos, 8) {
+        Some(offset) => offset as usize,
+        None => return anchors
+    };
+
+    let mut first_class = 0;
+    for lookup in 0 .. read_u16(gpos, lookup_list).unwrap_or(0) as usize {
+        let lookup = match read_u16(gpos, lookup_list + 2 + lookup * 2) {
+            Some(offset) => lookup_list + offset as usize,
+            None => break
+        };
+
+        let kind = read_u16(gpos, lookup).unwrap_or(0);
+        if kind != MARK_TO_BASE && kind != EXTENSION {
+            continue;
+        }
+
+        for subtable in 0 .. read_u16(gpos, lookup + 4).unwrap_or(0) as usize {
+            let mut subtable = match read_u16(gpos, lookup + 6 + subtable * 2) {
+                Some(offset) => lookup + offset as usize,
+                None => break
+            };
+
+            // extension subtables point to a subtable of another type with a 32 bits offset
+            if kind == EXTENSION {
+                if read_u16(gpos, subtable + 2) != Some(MARK_TO_BASE) {
+                    continue;
+                }
+                match read_u32(gpos, subtable + 4) {
+                    Some(offset) => subtable += offset as usize,
+                    None => continue
+                }
+            }
+
+            let classes = match mark_to_base(gpos, subtable, units_per_em, glyphs, first_class,
+                                             &mut anchors)
+            {
+                Some(classes) => classes,
+                None => continue
+            };
+
+            first_class += classes;
+        }
+    }
+
+    anchors
+}
+
+/// Reads a `MarkBasePosFormat1` subtable and returns its number of classes.
+fn mark_to_base(gpos: &[u8], subtable: usize, units_per_em: f32, glyphs: &[u32],
+                first_class: u32, anchors: &mut MarkAnchors) -> Option<u32>
+{
+    if read_u16(gpos, subtable)? != 1 {
+        return None;
+    }
+
+    let mark_coverage = subtable + read_u16(gpos, subtable + 2)? as usize;
+    let base_coverage = subtable + read_u16(gpos, subtable + 4)? as usize;
+    let classes = read_u16(gpos, subtable + 6)? as usize;
+    let mark_array = subtable + read_u16(gpos, subtable + 8)? as usize;
+    let base_array = subtable + read_u16(gpos, subtable + 10)? as usize;
+
+    let anchor = |offset: usize| -> Option<(f32, f32)> {
+        let x = read_u16(gpos, offset + 2)? as i16 as f32;
+        let y = read_u16(gpos, offset + 4)? as i16 as f32;
+        Some((x / units_per_em, y / units_per_em))
+    };
+
+    // the records that can't be read are skipped rather than the whole subtable, so that its
+    // classes are still counted and don't collide with those of the next subtables
+    for &glyph in glyphs {
+        if let Some(index) = coverage_index(gpos, mark_coverage, glyph) {
+            let record = mark_array + 2 + index * 4;
+            let mark = read_u16(gpos, record).and_then(|class| {
+                let offset = read_u16(gpos, record + 2)? as usize;
+                Some((class as usize, anchor(mark_array + offset)?))
+            });
+            match mark {
+                Some((class, position)) if class < classes => {
+                    anchors.marks.push((glyph, first_class + class as u32, position));
+                },
+                _ => ()
+            }
+        }
+
+        if let Some(index) = coverage_index(gpos, base_coverage, glyph) {
+            let record = base_array + 2 + index * classes * 2;
+            for class in 0 .. classes {
+                // a null offset means that this class of marks doesn't attach to the base
+                let offset = match read_u16(gpos, record + class * 2) {
+                    Some(0) | None => continue,
+                    Some(offset) => offset as usize
+                };
+                if let Some(position) = anchor(base_array + offset) {
+                    anchors.bases.push((glyph, first_class + class as u32, position));
+                }
+            }
+        }
+    }
+
+    Some(classes as u32)
+}
+
+/// Returns the glyphs that replace the given glyphs when a feature of a `GSUB` table is
+/// enabled, as pairs of the original glyph and its replacement.
+///
+/// The lookups of the feature are taken from all the scripts and languages, and only the
+/// single substitutions are applied.
+pub fn single_substitutions(gsub: &[u8], feature: &[u8; 4], glyphs: &[u32]) -> Vec<(u32, u32)> {
+    let mut substitutions = Vec::new();
+
+    let (feature_list, lookup_list) = match (read_u16(gsub, 6), read_u16(gsub, 8)) {
+        (Some(features), Some(lookups)) => (features as usize, lookups as usize),
+        _ => return substitutions
+    };
+
+    // the lookups of every feature record with this tag, applied in the order of the list
+    let mut lookups = Vec::new();
+    for record in 0 .. read_u16(gsub, feature_list).unwrap_or(0) as usize {
+        let record = feature_list + 2 + record * 6;
+        if gsub.get(record .. record + 4) != Some(&feature[..]) {
+            continue;
+        }
+
+        let table = match read_u16(gsub, record + 4) {
+            Some(offset) => feature_list + offset as usize,
+            None => break
+        };
+        for index in 0 .. read_u16(gsub, table + 2).unwrap_or(0) as usize {
+            if let Some(lookup) = read_u16(gsub, table + 4 + index * 2) {
+                lookups.push(lookup as usize);
+            }
+        }
+    }
+    lookups.sort();
+    lookups.dedup();
+
+    // the single substitution subtables of these lookups
+    let mut subtables = Vec::new();
+    for lookup in lookups {
+        let lookup = match read_u16(gsub, lookup_list + 2 + lookup * 2) {
+            Some(offset) => lookup_list + offset as usize,
+            None => continue
+        };
+
+        let kind = read_u16(gsub, lookup).unwrap_or(0);
+        if kind != SINGLE_SUBSTITUTION && kind != SUBSTITUTION_EXTENSION {
+            continue;
+        }
+
+        let mut lookup_subtables = Vec::new();
+        for subtable in 0 .. read_u16(gsub, lookup + 4).unwrap_or(0) as usize {
+            let mut subtable = match read_u16(gsub, lookup + 6 + subtable * 2) {
+                Some(offset) => lookup + offset as usize,
+                None => break
+            };
+
+            if kind == SUBSTITUTION_EXTENSION {
+                if read_u16(gsub, subtable + 2) != Some(SINGLE_SUBSTITUTION) {
+                    continue;
+                }
+                match read_u32(gsub, subtable + 4) {
+                    Some(offset) => subtable += offset as usize,
+                    None => continue
+                }
+            }
+
+            lookup_subtables.push(subtable);
+        }
+        subtables.push(lookup_subtables);
+    }
+
+    // each lookup replaces the result of the previous one, with its first matching subtable
+    for &glyph in glyphs {
+        let mut result = glyph;
+        for lookup_subtables in &subtables {
+            for &subtable in lookup_subtables {
+                if let Some(replacement) = single_substitution(gsub, subtable, result) {
+                    result = replacement;
+                    break;
+                }
+            }
+        }
+
+        if result != glyph {
+            substitutions.push((glyph, result));
+        }
+    }
+
+    substitutions
+}
+
+/// Applies a `SingleSubstFormat1` or `SingleSubstFormat2` subtable to a glyph.
+fn single_substitution(gsub: &[u8], subtable: usize, glyph: u32) -> Option<u32> {
+    let coverage = subtable + read_u16(gsub, subtable + 2)? as usize;
+    let index = coverage_index(gsub, coverage, glyph)?;
+
+    match read_u16(gsub, subtable)? {
+        1 => {
+            let delta = read_u16(gsub, subtable + 4)? as u32;
+            Some((glyph + delta) & 0xffff)
+        },
+        2 => {
+            if index >= read_u16(gsub, subtable + 4)? as usize {
+                return None;
+            }
+            read_u16(gsub, subtable + 6 + index * 2).map(|g| g as u32)
+        },
+        _ => None
+    }
+}
+
+/// Returns the index of a glyph in a coverage table.
+fn coverage_index(data: &[u8], coverage: usize, glyph: u32) -> Option<usize> {
+    match read_u16(data, coverage)? {
+        1 => {
+            for index in 0 .. read_u16(data, coverage + 2)? as usize {
+                if read_u16(data, coverage + 4 + index * 2)? as u32 == glyph {
+                    return Some(index);
+                }
+            }
+            None
+        },
+        2 => {
+            for range in 0 .. read_u16(data, coverage + 2)? as usize {
+                let record = coverage + 4 + range * 6;
+                let start = read_u16(data, record)? as u32;
+                let end = read_u16(data, record + 2)? as u32;
+                if glyph >= start && glyph <= end {
+                    let first_index = read_u16(data, record + 4)? as u32;
+                    return Some((first_index + glyph - start) as usize);
+                }
+            }
+            None
+        },
+        _ => None
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    if offset + 2 > data.len() {
+        return None;
+    }
+    Some(((data[offset] as u16) << 8) | data[offset + 1] as u16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let high = read_u16(data, offset)? as u32;
+    let low = read_u16(data, offset + 2)? as u32;
+    Some((high << 16) | low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coverage_index, mark_anchors, single_substitutions};
+
+    // the tables are written as lists of 16 bits words, in which the offsets are in bytes
+    fn bytes(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|&word| vec![(word >> 8) as u8, word as u8]).collect()
+    }
+
+    /// A lookup list of lookups given by their type and their subtables.
+    fn lookup_list(lookups: &[(u16, Vec<Vec<u16>>)]) -> Vec<u16> {
+        let mut list = vec![lookups.len() as u16];
+        let mut content: Vec<u16> = Vec::new();
+        for &(kind, ref subtables) in lookups {
+            list.push(2 * (1 + lookups.len() + content.len()) as u16);
+
+            let mut lookup = vec![kind, 0, subtables.len() as u16];
+            let mut lookup_content: Vec<u16> = Vec::new();
+            for subtable in subtables {
+                lookup.push(2 * (3 + subtables.len() + lookup_content.len()) as u16);
+                lookup_content.extend(subtable);
+            }
+            content.extend(lookup);
+            content.extend(lookup_content);
+        }
+        list.extend(content);
+        list
+    }
+
+    /// An extension subtable that contains a subtable of the given type.
+    fn extension(kind: u16, subtable: Vec<u16>) -> Vec<u16> {
+        let mut extension = vec![1, kind, 0, 8];
+        extension.extend(subtable);
+        extension
+    }
+
+    /// A `MarkBasePosFormat1` subtable with one mark, anchored at `(0, 500)`, and one base with
+    /// an anchor per class, or a null offset.
+    fn mark_to_base(mark: u16, mark_class: u16, base: u16, anchors: &[Option<(i16, i16)>])
+                    -> Vec<u16>
+    {
+        let classes = anchors.len() as u16;
+        let mut subtable = vec![
+            1, 12, 18, classes, 24, 36,
+            // mark and base coverage
+            1, 1, mark,
+            1, 1, base,
+            // mark array, with its anchor
+            1, mark_class, 6,
+            1, 0, 500,
+            // base array
+            1,
+        ];
+        for (class, anchor) in anchors.iter().enumerate() {
+            subtable.push(match *anchor {
+                Some(_) => 2 * (1 + classes + 3 * class as u16),
+                None => 0,
+            });
+        }
+        for anchor in anchors {
+            let (x, y) = anchor.unwrap_or((0, 0));
+            subtable.extend(&[1, x as u16, y as u16]);
+        }
+        subtable
+    }
+
+    fn gpos(lookups: &[(u16, Vec<Vec<u16>>)]) -> Vec<u8> {
+        let mut table = vec![1, 0, 0, 0, 10];
+        table.extend(lookup_list(lookups));
+        bytes(&table)
+    }
+
+    #[test]
+    fn coverage_formats() {
+        let format1 = bytes(&[1, 3, 5, 9, 12]);
+        assert_eq!(coverage_index(&format1, 0, 9), Some(1));
+        assert_eq!(coverage_index(&format1, 0, 12), Some(2));
+        assert_eq!(coverage_index(&format1, 0, 6), None);
+
+        // ranges with the coverage index of their first glyph
+        let format2 = bytes(&[2, 2, 10, 12, 0, 20, 20, 3]);
+        assert_eq!(coverage_index(&format2, 0, 11), Some(1));
+        assert_eq!(coverage_index(&format2, 0, 20), Some(3));
+        assert_eq!(coverage_index(&format2, 0, 13), None);
+
+        assert_eq!(coverage_index(&bytes(&[3, 1, 9]), 0, 9), None);
+        assert_eq!(coverage_index(&bytes(&[1, 5, 5]), 0, 7), None);
+        assert_eq!(coverage_index(&[], 0, 0), None);
+    }
+
+    #[test]
+    fn mark_anchors_classes() {
+        let table = gpos(&[
+            (4, vec![mark_to_base(10, 0, 20, &[Some((100, 200))])]),
+            (1, vec![vec![1, 6, 0, 1, 1, 20]]),
+            (9, vec![extension(4, mark_to_base(11, 1, 20, &[None, Some((300, -400))]))]),
+        ]);
+
+        // the classes of the second subtable follow the one of the first
+        let anchors = mark_anchors(&table, 1000, &[10, 11, 20, 30]);
+        assert_eq!(anchors.marks, vec![(10, 0, (0.0, 0.5)), (11, 2, (0.0, 0.5))]);
+        assert_eq!(anchors.bases, vec![(20, 0, (0.1, 0.2)), (20, 2, (0.3, -0.4))]);
+
+        // an extension to another type of lookup is skipped
+        let table = gpos(&[(9, vec![extension(5, mark_to_base(10, 0, 20, &[Some((1, 1))]))])]);
+        let anchors = mark_anchors(&table, 1000, &[10, 20]);
+        assert!(anchors.marks.is_empty() && anchors.bases.is_empty());
+    }
+
+    #[test]
+    fn mark_anchors_bad_records() {
+        // the mark array of the first subtable is out of the table, and the mark of the second
+        // one has a class that the subtable doesn't have
+        let mut first = mark_to_base(10, 0, 20, &[Some((100, 200))]);
+        first[4] = 0xfff0;
+        let table = gpos(&[
+            (4, vec![first, mark_to_base(12, 1, 22, &[Some((100, 200))])]),
+            (4, vec![mark_to_base(11, 0, 21, &[Some((300, 400))])]),
+        ]);
+
+        // the classes of the first subtables are still counted
+        let anchors = mark_anchors(&table, 1000, &[20, 10, 12, 22, 11, 21]);
+        assert_eq!(anchors.marks, vec![(11, 2, (0.0, 0.5))]);
+        assert_eq!(anchors.bases, vec![(20, 0, (0.1, 0.2)), (22, 1, (0.1, 0.2)),
+                                       (21, 2, (0.3, 0.4))]);
+    }
+
+    #[test]
+    fn mark_anchors_malformed() {
+        let table = gpos(&[(4, vec![mark_to_base(10, 0, 20, &[Some((100, 200))])])]);
+        for length in 0 .. table.len() {
+            let anchors = mark_anchors(&table[.. length], 1000, &[10, 20]);
+            assert!(anchors.marks.len() <= 1 && anchors.bases.len() <= 1);
+        }
+
+        for &byte in &[0x00, 0x01, 0x7f, 0xff] {
+            let anchors = mark_anchors(&[byte; 64], 1000, &[0, 1, 0xffff]);
+            assert!(anchors.marks.is_empty() && anchors.bases.is_empty());
+        }
+        let anchors = mark_anchors(&[], 1000, &[10]);
+        assert!(anchors.marks.is_empty() && anchors.bases.is_empty());
+    }
+
+    fn gsub(features: &[(&[u8; 4], Vec<u16>)], lookups: &[(u16, Vec<Vec<u16>>)]) -> Vec<u8> {
+        let mut list = vec![features.len() as u16];
+        let mut tables: Vec<u16> = Vec::new();
+        for &(tag, ref indices) in features {
+            let offset = 2 * (1 + 3 * features.len() + tables.len()) as u16;
+            list.extend(&[(tag[0] as u16) << 8 | tag[1] as u16,
+                          (tag[2] as u16) << 8 | tag[3] as u16, offset]);
+            tables.extend(&[0, indices.len() as u16]);
+            tables.extend(indices);
+        }
+        list.extend(tables);
+
+        let mut table = vec![1, 0, 0, 10, 10 + 2 * list.len() as u16];
+        table.extend(list);
+        table.extend(lookup_list(lookups));
+        bytes(&table)
+    }
+
+    /// A `SingleSubstFormat1` subtable for a glyph.
+    fn delta(glyph: u16, delta: u16) -> Vec<u16> {
+        vec![1, 6, delta, 1, 1, glyph]
+    }
+
+    #[test]
+    fn single_substitution_formats() {
+        // the deltas wrap around
+        let lookups = [(1, vec![delta(0xfff0, 0x20), delta(5, 0xffff)])];
+        let table = gsub(&[(b"smcp", vec![0])], &lookups);
+        assert_eq!(single_substitutions(&table, b"smcp", &[0xfff0, 5, 6]),
+                   vec![(0xfff0, 0x10), (5, 4)]);
+
+        // the second covered glyph has no replacement
+        let format2 = vec![2, 8, 1, 40, 1, 2, 10, 11];
+        let table = gsub(&[(b"smcp", vec![0])], &[(1, vec![format2])]);
+        assert_eq!(single_substitutions(&table, b"smcp", &[10, 11]), vec![(10, 40)]);
+        assert!(single_substitutions(&table, b"tnum", &[10, 11]).is_empty());
+    }
+
+    #[test]
+    fn single_substitution_lookups() {
+        // the lookups are applied in the order of the lookup list, not of the feature
+        let lookups = [(1, vec![delta(10, 1)]), (1, vec![delta(11, 1), delta(11, 5)])];
+        let table = gsub(&[(b"ss01", vec![1, 0])], &lookups);
+        assert_eq!(single_substitutions(&table, b"ss01", &[10, 11, 12]), vec![(10, 12), (11, 12)]);
+
+        // the extensions of single substitutions are followed, the ligatures are ignored
+        let lookups = [(7, vec![extension(1, delta(10, 3))]),
+                       (7, vec![extension(4, delta(11, 3))]),
+                       (4, vec![vec![1, 8, 0, 0, 1, 1, 12]])];
+        let table = gsub(&[(b"ss01", vec![0, 1]), (b"liga", vec![2])], &lookups);
+        assert_eq!(single_substitutions(&table, b"ss01", &[10, 11, 12]), vec![(10, 13)]);
+        assert!(single_substitutions(&table, b"liga", &[10, 11, 12]).is_empty());
+
+        for length in 0 .. table.len() {
+            assert!(single_substitutions(&table[.. length], b"ss01", &[10]).len() <= 1);
+        }
+    }
+}
@@ -0,0 +1,830 @@
+//! Glyph source written in pure Rust, based on `ttf-parser` and `ab_glyph_rasterizer`.
+
+use ab_glyph_rasterizer::{point, Point, Rasterizer};
+use png;
+use ttf_parser;
+
+use std::collections::HashSet;
+
+use super::{bgra_to_rgba, decode_name, resize_rgba, tag_to_bytes};
+use super::{BitmapFormat, Encoding, GlyphBitmap, GlyphSource, MarkAnchors};
+use {FontMetrics, NamedInstance, RasterOptions, SubpixelOrder, VariationAxis};
+#[cfg(feature = "system-fonts")]
+use super::FaceInfo;
+
+/// A face parsed by `ttf-parser`.
+pub struct Face<'a> {
+    #[cfg_attr(not(feature = "system-fonts"), allow(dead_code))]
+    data: &'a [u8],
+    face: ttf_parser::Face<'a>,
+    // number of pixels per EM, and per font unit
+    pixel_size: u32,
+    scale: f32,
+    // `None` for grayscale antialiasing
+    subpixel: Option<SubpixelOrder>,
+    raster: RasterOptions,
+    // offset of the origin of the glyph being rasterized, in pixels
+    x_offset: f32,
+}
+
+impl<'a> Face<'a> {
+    /// Loads the face with the given index from the data of a font file.
+    pub fn new(font: &'a [u8], face_index: u32) -> Result<Face<'a>, ()> {
+        let face = match ttf_parser::Face::parse(font, face_index) {
+            Ok(f) => f,
+            Err(_) => return Err(())
+        };
+
+        Ok(Face {
+            data: font,
+            face: face,
+            pixel_size: 0,
+            scale: 1.0,
+            subpixel: None,
+            raster: RasterOptions::default(),
+            x_offset: 0.0,
+        })
+    }
+
+    /// Returns an entry of the `name` table, preferably in English.
+    fn name(&self, name_id: u16) -> Option<String> {
+        let mut result = None;
+
+        for name in self.face.names() {
+            if name.name_id != name_id {
+                continue;
+            }
+
+            let platform_id = match name.platform_id {
+                ttf_parser::PlatformId::Unicode => 0,
+                ttf_parser::PlatformId::Macintosh => 1,
+                ttf_parser::PlatformId::Windows => 3,
+                _ => continue
+            };
+
+            if let Some(decoded) = decode_name(platform_id, name.name) {
+                let english = name.language_id == 0x409 || name.language_id == 0;
+                if english || result.is_none() {
+                    result = Some(decoded);
+                }
+                if english {
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Collects the outline of a glyph. Returns `None` if the glyph has no outline.
+    ///
+    /// The synthetic styles of the raster options are applied to the outline.
+    fn outline(&self, glyph: ttf_parser::GlyphId) -> Option<Outline> {
+        let units_per_em = self.face.units_per_em() as f32;
+
+        // same strengths as `FT_GlyphSlot_Oblique` and `FT_GlyphSlot_Embolden`
+        let mut outline = Outline {
+            segments: Vec::new(),
+            bbox: None,
+            slant: if self.raster.oblique { 0x0366A as f32 / 65536.0 } else { 0.0 },
+            bold: if self.raster.embolden { units_per_em / 24.0 } else { 0.0 },
+            shift: self.x_offset,
+            start: point(0.0, 0.0),
+            last: point(0.0, 0.0),
+        };
+        let bbox = self.face.outline_glyph(glyph, &mut outline)?;
+
+        // the bounding box of the font doesn't take the slant into account, so we use the
+        // points of the outline instead
+        outline.bbox = Some(if outline.slant != 0.0 { outline.points_bounds() } else { bbox });
+        Some(outline)
+    }
+
+    /// Loads the image of a glyph from the `sbix` or `CBDT` tables, and scales it to the
+    /// current size.
+    fn raster_image(&self, glyph: ttf_parser::GlyphId, advance: f32) -> Option<GlyphBitmap> {
+        let size = self.pixel_size.min(u16::max_value() as u32) as u16;
+        let image = self.face.glyph_raster_image(glyph, size)?;
+        if image.pixels_per_em == 0 {
+            return None;
+        }
+
+        let (width, rows, buffer) = match image.format {
+            ttf_parser::RasterImageFormat::PNG => decode_png(image.data)?,
+            ttf_parser::RasterImageFormat::BitmapPremulBgra32 => {
+                let (width, rows) = (image.width as u32, image.height as u32);
+                let mut buffer = image.data.get(.. (width * rows * 4) as usize)?.to_vec();
+                bgra_to_rgba(&mut buffer);
+                (width, rows, buffer)
+            },
+            // the monochrome and grayscale strikes are only used by old fonts that also have
+            // outlines
+            _ => return None
+        };
+
+        // the images are stored at the size of the strike, and `y` is the position of their
+        // bottom row
+        let scale = self.pixel_size as f32 / image.pixels_per_em as f32;
+        let new_width = ((width as f32 * scale).round() as u32).max(1);
+        let new_rows = ((rows as f32 * scale).round() as u32).max(1);
+
+        Some(GlyphBitmap {
+            width: new_width,
+            rows: new_rows,
+            left: (image.x as f32 * scale).round() as i32,
+            top: ((image.y as f32 + rows as f32) * scale).round() as i32,
+            advance: advance,
+            format: BitmapFormat::Rgba,
+            buffer: resize_rgba(&buffer, width, rows, new_width, new_rows),
+        })
+    }
+
+    /// Draws the layers of a glyph of the `COLR` table on top of each other.
+    fn color_layers(&self, glyph: ttf_parser::GlyphId, advance: f32) -> Option<GlyphBitmap> {
+        let mut layers = Layers { face: self, current: None, layers: Vec::new() };
+        self.face.paint_color_glyph(glyph, 0, &mut layers)?;
+        let layers = layers.layers;
+
+        // the bitmap must cover all the layers
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for &(ref outline, _) in &layers {
+            let PixelBounds { left, top, width, rows } = outline.pixel_bounds(self.scale);
+            let (right, bottom) = (left + width as i32, top - rows as i32);
+            bounds = Some(match bounds {
+                Some((l, t, r, b)) => (l.min(left), t.max(top), r.max(right), b.min(bottom)),
+                None => (left, top, right, bottom),
+            });
+        }
+
+        let (left, top, right, bottom) = bounds?;
+        let (width, rows) = ((right - left) as u32, (top - bottom) as u32);
+        let bounds = PixelBounds { left: left, top: top, width: width, rows: rows };
+
+        // compositing with the "over" operator, in premultiplied alpha
+        let mut premultiplied = vec![0.0f32; (width * rows * 4) as usize];
+        for (outline, color) in layers {
+            let coverage = outline.coverage(self.scale, bounds);
+            for (index, &c) in coverage.iter().enumerate() {
+                let alpha = c as f32 / 255.0 * color[3];
+                let pixel = &mut premultiplied[index * 4 .. index * 4 + 4];
+                for channel in 0 .. 3 {
+                    pixel[channel] = color[channel] * alpha + pixel[channel] * (1.0 - alpha);
+                }
+                pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+            }
+        }
+
+        let mut buffer = Vec::with_capacity(premultiplied.len());
+        for pixel in premultiplied.chunks(4) {
+            let alpha = pixel[3];
+            for channel in 0 .. 3 {
+                let value = if alpha == 0.0 { 0.0 } else { pixel[channel] / alpha };
+                buffer.push((value.min(1.0) * 255.0).round() as u8);
+            }
+            buffer.push((alpha.min(1.0) * 255.0).round() as u8);
+        }
+
+        Some(GlyphBitmap {
+            width: width,
+            rows: rows,
+            left: left,
+            top: top,
+            advance: advance,
+            format: BitmapFormat::Rgba,
+            buffer: buffer,
+        })
+    }
+}
+
+impl<'a> GlyphSource for Face<'a> {
+    fn set_pixel_size(&mut self, font_size: u32) -> Result<(), ()> {
+        let units_per_em = self.face.units_per_em();
+        if units_per_em == 0 {
+            return Err(());
+        }
+
+        self.pixel_size = font_size;
+        self.scale = font_size as f32 / units_per_em as f32;
+        Ok(())
+    }
+
+    fn metrics(&self) -> FontMetrics {
+        let em = self.face.units_per_em() as f32;
+
+        // height of a glyph above the base line
+        let glyph_top = |chr: char| {
+            self.face.glyph_index(chr)
+                     .and_then(|glyph| self.face.glyph_bounding_box(glyph))
+                     .map(|bbox| bbox.y_max as f32 / em)
+        };
+
+        let ascender = self.face.ascender() as f32 / em;
+        let cap_height = match self.face.capital_height() {
+            Some(height) if height > 0 => Some(height as f32 / em),
+            _ => glyph_top('H'),
+        }.unwrap_or(ascender);
+        let x_height = match self.face.x_height() {
+            Some(height) if height > 0 => Some(height as f32 / em),
+            _ => glyph_top('x'),
+        }.unwrap_or(cap_height * 2.0 / 3.0);
+
+        // the `post` table gives the top of the underline, and FreeType its center
+        let (underline_position, underline_thickness) = match self.face.underline_metrics() {
+            Some(metrics) => {
+                let thickness = metrics.thickness as f32;
+                ((metrics.position as f32 - thickness / 2.0) / em, thickness / em)
+            },
+            None => (0.0, 0.0),
+        };
+
+        FontMetrics {
+            ascender: ascender,
+            descender: self.face.descender() as f32 / em,
+            line_gap: self.face.line_gap() as f32 / em,
+            cap_height: cap_height,
+            x_height: x_height,
+            underline_position: underline_position,
+            underline_thickness: underline_thickness,
+        }
+    }
+
+    fn characters(&self) -> Vec<(char, u32)> {
+        let mut result = Vec::new();
+        let mut found = HashSet::new();
+
+        let subtables = match self.face.tables().cmap {
+            Some(cmap) => cmap.subtables,
+            None => return result
+        };
+
+        // using all the Unicode subtables, so that a font with both a BMP-only table and a
+        // full-repertoire table gets its supplementary-plane characters ; falling back to the
+        // legacy encodings only when there's no Unicode table
+        let encoding = if subtables.into_iter().any(|s| s.is_unicode()) {
+            Encoding::Unicode
+        } else if subtables.into_iter().any(|s| is_symbol(&s)) {
+            Encoding::Symbol
+        } else if subtables.into_iter().any(|s| is_mac_roman(&s)) {
+            Encoding::MacRoman
+        } else {
+            Encoding::Other
+        };
+
+        for subtable in subtables {
+            let matches = match encoding {
+                Encoding::Unicode => subtable.is_unicode(),
+                Encoding::Symbol => is_symbol(&subtable),
+                Encoding::MacRoman => is_mac_roman(&subtable),
+                Encoding::Other => true,
+            };
+
+            if !matches {
+                continue;
+            }
+
+            subtable.codepoints(|code| {
+                let glyph = match subtable.glyph_index(code) {
+                    Some(glyph) if glyph.0 != 0 => glyph.0 as u32,
+                    _ => return
+                };
+
+                encoding.decode(code, |chr| {
+                    if found.insert(chr) {
+                        result.push((chr, glyph));
+                    }
+                });
+            });
+        }
+
+        result
+    }
+
+    fn set_raster_options(&mut self, options: &RasterOptions, subpixel: Option<SubpixelOrder>) {
+        // there is no hinting, so `options.hinting` is ignored
+        self.subpixel = subpixel;
+        self.raster = *options;
+    }
+
+    fn rasterize(&mut self, glyph: u32, x_offset: f32) -> Option<GlyphBitmap> {
+        self.x_offset = x_offset;
+        let glyph = ttf_parser::GlyphId(glyph as u16);
+        let advance = self.face.glyph_hor_advance(glyph).unwrap_or(0) as f32 * self.scale;
+
+        // color glyphs come first, as emoji fonts often also have a monochrome outline
+        if let Some(bitmap) = self.raster_image(glyph, advance) {
+            return Some(bitmap);
+        }
+        if self.face.is_color_glyph(glyph) {
+            if let Some(bitmap) = self.color_layers(glyph, advance) {
+                return Some(bitmap);
+            }
+        }
+
+        let outline = match self.outline(glyph) {
+            Some(outline) => outline,
+            None => {
+                // glyphs without an outline, like spaces
+                return Some(GlyphBitmap {
+                    width: 0,
+                    rows: 0,
+                    left: 0,
+                    top: 0,
+                    advance: advance,
+                    format: BitmapFormat::Coverage,
+                    buffer: Vec::new(),
+                });
+            }
+        };
+
+        let bounds = outline.pixel_bounds(self.scale);
+
+        // the emboldened glyphs are wider, so they need more room
+        let advance = advance + outline.bold * self.scale;
+
+        if let Some(order) = self.subpixel {
+            return Some(outline.lcd_coverage(self.scale, order.is_vertical(), bounds, advance));
+        }
+
+        let mut buffer = outline.coverage(self.scale, bounds);
+        if !self.raster.antialiasing {
+            for value in buffer.iter_mut() {
+                *value = if *value >= 128 { 255 } else { 0 };
+            }
+        }
+
+        Some(GlyphBitmap {
+            width: bounds.width,
+            rows: bounds.rows,
+            left: bounds.left,
+            top: bounds.top,
+            advance: advance,
+            format: BitmapFormat::Coverage,
+            buffer: buffer,
+        })
+    }
+
+    fn variation_axes(&self) -> Vec<VariationAxis> {
+        self.face.variation_axes().into_iter().map(|axis| {
+            let tag = tag_to_bytes(axis.tag.0);
+            VariationAxis {
+                tag: tag,
+                name: self.name(axis.name_id)
+                          .unwrap_or_else(|| String::from_utf8_lossy(&tag).into_owned()),
+                min: axis.min_value,
+                default: axis.def_value,
+                max: axis.max_value,
+            }
+        }).collect()
+    }
+
+    fn named_instances(&self) -> Vec<NamedInstance> {
+        // `ttf-parser` doesn't expose the instances, so we read the `fvar` table ourselves
+        let fvar = match self.face.raw_face().table(ttf_parser::Tag::from_bytes(b"fvar")) {
+            Some(fvar) => fvar,
+            None => return Vec::new()
+        };
+
+        let read_u16 = |offset: usize| fvar.get(offset .. offset + 2)
+                                           .map(|b| ((b[0] as u16) << 8) | b[1] as u16);
+        let read_fixed = |offset: usize| fvar.get(offset .. offset + 4).map(|b| {
+            (((b[0] as u32) << 24) | ((b[1] as u32) << 16) | ((b[2] as u32) << 8) | b[3] as u32)
+                as i32 as f32 / 65536.0
+        });
+
+        let header = (read_u16(4), read_u16(8), read_u16(10), read_u16(12), read_u16(14));
+        let (axes_offset, axes_count, axis_size, instances_count, instance_size) = match header {
+            (Some(a), Some(b), Some(c), Some(d), Some(e)) => {
+                (a as usize, b as usize, c as usize, d as usize, e as usize)
+            },
+            _ => return Vec::new()
+        };
+
+        // the instances immediately follow the axes
+        let instances_offset = axes_offset + axes_count * axis_size;
+
+        (0 .. instances_count).filter_map(|index| {
+            let offset = instances_offset + index * instance_size;
+            let name_id = read_u16(offset)?;
+            let coordinates = (0 .. axes_count).map(|axis| read_fixed(offset + 4 + axis * 4))
+                                               .collect::<Option<Vec<f32>>>()?;
+
+            Some(NamedInstance {
+                name: self.name(name_id).unwrap_or_default(),
+                coordinates: coordinates,
+            })
+        }).collect()
+    }
+
+    fn set_variations(&mut self, coordinates: &[f32]) -> Result<(), ()> {
+        let tags: Vec<_> = self.face.variation_axes().into_iter().map(|a| a.tag).collect();
+        for (tag, &value) in tags.into_iter().zip(coordinates.iter()) {
+            if self.face.set_variation(tag, value).is_none() {
+                return Err(());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mark_anchors(&self, glyphs: &[u32]) -> MarkAnchors {
+        match self.face.raw_face().table(ttf_parser::Tag::from_bytes(b"GPOS")) {
+            Some(gpos) => super::opentype::mark_anchors(gpos, self.face.units_per_em(), glyphs),
+            None => MarkAnchors::default()
+        }
+    }
+
+    fn substitutions(&self, feature: &[u8; 4], glyphs: &[u32]) -> Vec<(u32, u32)> {
+        match self.face.raw_face().table(ttf_parser::Tag::from_bytes(b"GSUB")) {
+            Some(gsub) => super::opentype::single_substitutions(gsub, feature, glyphs),
+            None => Vec::new()
+        }
+    }
+
+    fn vertical_metrics(&self, glyph: u32) -> Option<(f32, f32)> {
+        let glyph = ttf_parser::GlyphId(glyph as u16);
+        let advance = self.face.glyph_ver_advance(glyph)? as f32;
+        let top_side_bearing = self.face.glyph_ver_side_bearing(glyph)? as f32;
+
+        // the side bearing is measured from the top of the glyph, and empty glyphs have none
+        let top = self.face.glyph_bounding_box(glyph).map_or(0.0, |bbox| bbox.y_max as f32);
+
+        let em = self.face.units_per_em() as f32;
+        Some((advance / em, (top_side_bearing + top) / em))
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn faces_count(&self) -> u32 {
+        ttf_parser::fonts_in_collection(self.data).unwrap_or(1)
+    }
+
+    #[cfg(feature = "system-fonts")]
+    fn info(&self) -> FaceInfo {
+        // the typographic names group all the weights of a family, like FreeType does
+        let name = |preferred: u16, fallback: u16| {
+            self.name(preferred).or_else(|| self.name(fallback)).unwrap_or_default()
+        };
+
+        FaceInfo {
+            family: name(ttf_parser::name_id::TYPOGRAPHIC_FAMILY, ttf_parser::name_id::FAMILY),
+            style: name(ttf_parser::name_id::TYPOGRAPHIC_SUBFAMILY,
+                        ttf_parser::name_id::SUBFAMILY),
+            weight: self.face.weight().to_number(),
+            width: self.face.width().to_number(),
+            italic: self.face.is_italic() || self.face.is_oblique(),
+        }
+    }
+}
+
+fn is_symbol(subtable: &ttf_parser::cmap::Subtable) -> bool {
+    subtable.platform_id == ttf_parser::PlatformId::Windows && subtable.encoding_id == 0
+}
+
+fn is_mac_roman(subtable: &ttf_parser::cmap::Subtable) -> bool {
+    subtable.platform_id == ttf_parser::PlatformId::Macintosh && subtable.encoding_id == 0
+}
+
+enum Segment {
+    Line(Point, Point),
+    Quad(Point, Point, Point),
+    Cubic(Point, Point, Point, Point),
+}
+
+// pixels covered by an outline, with the left column and the top row
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PixelBounds {
+    left: i32,
+    top: i32,
+    width: u32,
+    rows: u32,
+}
+
+// collects the segments of an outline, in font units
+struct Outline {
+    segments: Vec<Segment>,
+    bbox: Option<ttf_parser::Rect>,
+    // horizontal shift of the points for each unit of height, for the oblique style
+    slant: f32,
+    // number of units by which the glyph is made thicker, for the bold style
+    bold: f32,
+    // number of pixels by which the glyph is moved to the right
+    shift: f32,
+    start: Point,
+    last: Point,
+}
+
+impl Outline {
+    // returns the pixels covered by the outline
+    fn pixel_bounds(&self, scale: f32) -> PixelBounds {
+        let bbox = match self.bbox {
+            Some(bbox) => bbox,
+            None => return PixelBounds { left: 0, top: 0, width: 0, rows: 0 }
+        };
+
+        // emboldening grows the glyph to the right and to the top
+        let bold = (self.bold * scale).ceil() as i32;
+
+        let left = (bbox.x_min as f32 * scale + self.shift).floor() as i32;
+        let right = (bbox.x_max as f32 * scale + self.shift).ceil() as i32 + bold;
+        let bottom = (bbox.y_min as f32 * scale).floor() as i32;
+        let top = (bbox.y_max as f32 * scale).ceil() as i32 + bold;
+        PixelBounds {
+            left: left,
+            top: top,
+            width: (right - left).max(0) as u32,
+            rows: (top - bottom).max(0) as u32,
+        }
+    }
+
+    // returns the bounding box of the points of the outline, including the control points
+    fn points_bounds(&self) -> ttf_parser::Rect {
+        let (mut x_min, mut y_min) = (0.0f32, 0.0f32);
+        let (mut x_max, mut y_max) = (0.0f32, 0.0f32);
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            let points = match *segment {
+                Segment::Line(p0, p1) => vec![p0, p1],
+                Segment::Quad(p0, p1, p2) => vec![p0, p1, p2],
+                Segment::Cubic(p0, p1, p2, p3) => vec![p0, p1, p2, p3],
+            };
+
+            for (n, p) in points.into_iter().enumerate() {
+                if index == 0 && n == 0 {
+                    x_min = p.x; x_max = p.x; y_min = p.y; y_max = p.y;
+                }
+                x_min = x_min.min(p.x);
+                x_max = x_max.max(p.x);
+                y_min = y_min.min(p.y);
+                y_max = y_max.max(p.y);
+            }
+        }
+
+        ttf_parser::Rect {
+            x_min: x_min.floor() as i16,
+            y_min: y_min.floor() as i16,
+            x_max: x_max.ceil() as i16,
+            y_max: y_max.ceil() as i16,
+        }
+    }
+
+    // draws the outline in a bitmap that covers `bounds`
+    fn coverage(&self, scale: f32, bounds: PixelBounds) -> Vec<u8> {
+        self.oversampled_coverage(scale, (1, 1), bounds)
+    }
+
+    // draws the outline with one byte per subpixel, and filters the result to reduce the color
+    // fringes like FreeType's default LCD filter
+    fn lcd_coverage(&self, scale: f32, vertical: bool, bounds: PixelBounds, advance: f32)
+                    -> GlyphBitmap
+    {
+        // the filter spreads each subpixel over its neighbours, so we add one pixel on each side
+        let PixelBounds { left, top, width, rows } = bounds;
+        let (left, top) = if vertical { (left, top + 1) } else { (left - 1, top) };
+        let (width, rows) = if vertical { (width, rows + 2) } else { (width + 2, rows) };
+
+        let factors = if vertical { (1, 3) } else { (3, 1) };
+        let bounds = PixelBounds { left: left, top: top, width: width, rows: rows };
+        let samples = self.oversampled_coverage(scale, factors, bounds);
+        let (samples_width, samples_rows) = (width * factors.0, rows * factors.1);
+
+        const FILTER: [u32; 5] = [0x08, 0x4d, 0x56, 0x4d, 0x08];
+        let sample = |x: i32, y: i32| {
+            if x < 0 || y < 0 || x >= samples_width as i32 || y >= samples_rows as i32 {
+                0
+            } else {
+                samples[(y as u32 * samples_width + x as u32) as usize] as u32
+            }
+        };
+
+        let mut buffer = Vec::with_capacity((width * rows * 3) as usize);
+        for y in 0 .. rows as i32 {
+            for x in 0 .. width as i32 {
+                for subpixel in 0 .. 3 {
+                    let mut sum = 0;
+                    for (offset, weight) in FILTER.iter().enumerate() {
+                        let offset = offset as i32 - 2;
+                        sum += weight * if vertical {
+                            sample(x, y * 3 + subpixel + offset)
+                        } else {
+                            sample(x * 3 + subpixel + offset, y)
+                        };
+                    }
+                    buffer.push((sum / 256).min(255) as u8);
+                }
+            }
+        }
+
+        GlyphBitmap {
+            width: width,
+            rows: rows,
+            left: left,
+            top: top,
+            advance: advance,
+            format: BitmapFormat::Lcd,
+            buffer: buffer,
+        }
+    }
+
+    // draws the outline with `factors.0` samples per pixel horizontally and `factors.1`
+    // vertically
+    fn oversampled_coverage(&self, scale: f32, factors: (u32, u32), bounds: PixelBounds)
+                            -> Vec<u8>
+    {
+        let PixelBounds { left, top, width, rows } = bounds;
+        let (width, rows) = (width * factors.0, rows * factors.1);
+        let (factor_x, factor_y) = (factors.0 as f32, factors.1 as f32);
+
+        // converting from font units with the Y axis going up to pixels with the Y axis going down
+        let shift = self.shift;
+        let to_pixels = |p: Point| point((p.x * scale + shift - left as f32) * factor_x,
+                                         (top as f32 - p.y * scale) * factor_y);
+
+        let mut rasterizer = Rasterizer::new(width as usize, rows as usize);
+        for segment in &self.segments {
+            match *segment {
+                Segment::Line(p0, p1) => rasterizer.draw_line(to_pixels(p0), to_pixels(p1)),
+                Segment::Quad(p0, p1, p2) => {
+                    rasterizer.draw_quad(to_pixels(p0), to_pixels(p1), to_pixels(p2))
+                },
+                Segment::Cubic(p0, p1, p2, p3) => {
+                    rasterizer.draw_cubic(to_pixels(p0), to_pixels(p1), to_pixels(p2),
+                                          to_pixels(p3))
+                },
+            }
+        }
+
+        let mut buffer = vec![0u8; (width * rows) as usize];
+        rasterizer.for_each_pixel(|index, coverage| {
+            buffer[index] = (coverage.min(1.0) * 255.0).round() as u8;
+        });
+
+        if self.bold != 0.0 {
+            buffer = dilate(&buffer, width, rows, self.bold * scale * factor_x,
+                            self.bold * scale * factor_y);
+        }
+
+        buffer
+    }
+}
+
+// records the layers of a `COLR` glyph, with their color as RGBA between 0 and 1
+struct Layers<'f, 'a: 'f> {
+    face: &'f Face<'a>,
+    current: Option<Outline>,
+    layers: Vec<(Outline, [f32; 4])>,
+}
+
+impl<'f, 'a> ttf_parser::colr::Painter for Layers<'f, 'a> {
+    fn outline(&mut self, glyph_id: ttf_parser::GlyphId) {
+        self.current = self.face.outline(glyph_id);
+    }
+
+    fn paint_foreground(&mut self) {
+        // color glyphs keep their own colors when drawn, so the foreground is always black
+        self.paint_color(ttf_parser::RgbaColor::new(0, 0, 0, 255));
+    }
+
+    fn paint_color(&mut self, color: ttf_parser::RgbaColor) {
+        if let Some(outline) = self.current.take() {
+            let color = [color.red as f32 / 255.0, color.green as f32 / 255.0,
+                         color.blue as f32 / 255.0, color.alpha as f32 / 255.0];
+            self.layers.push((outline, color));
+        }
+    }
+}
+
+// makes the shapes of a coverage bitmap thicker by `x` samples to the right and `y` samples to
+// the top, like `FT_Bitmap_Embolden`
+fn dilate(buffer: &[u8], width: u32, rows: u32, x: f32, y: f32) -> Vec<u8> {
+    // each sample takes the maximum of the samples at its left and below it, the farthest one
+    // being weighted by the fractional part of the strength
+    let shifts = |strength: f32| {
+        let mut shifts: Vec<(u32, f32)> = (0 .. strength as u32 + 1).map(|s| (s, 1.0)).collect();
+        if strength.fract() > 0.0 {
+            shifts.push((strength as u32 + 1, strength.fract()));
+        }
+        shifts
+    };
+
+    let (shifts_x, shifts_y) = (shifts(x), shifts(y));
+
+    let mut result = vec![0u8; buffer.len()];
+    for row in 0 .. rows {
+        for column in 0 .. width {
+            let mut value = 0.0f32;
+            for &(dx, wx) in &shifts_x {
+                for &(dy, wy) in &shifts_y {
+                    if dx > column || row + dy >= rows {
+                        continue;
+                    }
+                    let sample = buffer[((row + dy) * width + column - dx) as usize] as f32;
+                    value = value.max(sample * wx * wy);
+                }
+            }
+            result[(row * width + column) as usize] = value.round() as u8;
+        }
+    }
+
+    result
+}
+
+// decodes a PNG image to RGBA, returning its width, height and pixels
+fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut pixels = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).ok()?;
+
+    let pixels = &pixels[.. info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => {
+            pixels.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect()
+        },
+        png::ColorType::GrayscaleAlpha => {
+            pixels.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect()
+        },
+        png::ColorType::Grayscale => {
+            pixels.iter().flat_map(|&p| vec![p, p, p, 255]).collect()
+        },
+        png::ColorType::Indexed => return None,
+    };
+
+    Some((info.width, info.height, rgba))
+}
+
+impl Outline {
+    // applies the slant to a point
+    fn point(&self, x: f32, y: f32) -> Point {
+        point(x + y * self.slant, y)
+    }
+}
+
+impl ttf_parser::OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.point(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.segments.push(Segment::Line(self.last, p));
+        self.last = p;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let p = self.point(x, y);
+        let p1 = self.point(x1, y1);
+        self.segments.push(Segment::Quad(self.last, p1, p));
+        self.last = p;
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let p = self.point(x, y);
+        let (p1, p2) = (self.point(x1, y1), self.point(x2, y2));
+        self.segments.push(Segment::Cubic(self.last, p1, p2, p));
+        self.last = p;
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.segments.push(Segment::Line(self.last, self.start));
+        }
+        self.last = self.start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Face;
+    use backend::{BitmapFormat, GlyphSource};
+
+    #[test]
+    fn rasterize_glyphs() {
+        let font = include_bytes!("../../examples/font.ttf");
+        let mut face = Face::new(&font[..], 0).unwrap();
+        face.set_pixel_size(32).unwrap();
+        face.set_raster_options(&Default::default(), None);
+        let characters = face.characters();
+
+        // the advances aren't hinted, so they are the ones of the font scaled to 32 pixels per
+        // EM of 1000 units ; the bitmaps cover the outlines, without hinting either
+        for &(character, advance, left, top, width, rows) in &[('o', 570, 1, 16, 16, 17),
+                                                               ('g', 600, 1, 16, 16, 24),
+                                                               ('W', 1054, 0, 25, 34, 25),
+                                                               ('.', 154, 0, 4, 5, 5),
+                                                               (' ', 280, 0, 0, 0, 0)]
+        {
+            let glyph = characters.iter().find(|&&(c, _)| c == character).unwrap().1;
+            let bitmap = face.rasterize(glyph, 0.0).unwrap();
+            assert!((bitmap.advance - advance as f32 * 0.032).abs() < 1e-3, "{:?}", character);
+            assert_eq!((bitmap.left, bitmap.top, bitmap.width, bitmap.rows),
+                       (left, top, width, rows), "{:?}", character);
+            assert_eq!(bitmap.format, BitmapFormat::Coverage);
+            assert_eq!(bitmap.buffer.len(), (width * rows) as usize);
+            if width != 0 {
+                assert_eq!(bitmap.buffer.iter().cloned().max(), Some(255));
+            }
+        }
+    }
+}
@@ -0,0 +1,1634 @@
+//! Positioning of the glyphs of a text.
+//!
+//! The layout only needs the metrics stored in the `FontTexture`, which are read through the
+//! `GlyphMetrics` trait, so the same code is used to build the vertices of a `TextDisplay` and
+//! to measure a text without any GPU resource.
+//!
+//! Each line is split into runs of the same direction with the Unicode bidirectional algorithm,
+//! and the runs are laid out in visual order. The layout also keeps the position of each
+//! grapheme cluster, so that positions on the screen can be converted to offsets in the text and
+//! the other way around.
+
+use std::ops::Range;
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use {CharacterInfos, FontMetrics};
+
+/// Options for laying out a text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutOptions {
+    /// Multiplier of the distance between the base lines of two consecutive lines, which is
+    /// `FontMetrics::line_height`. The default is `1.0`.
+    ///
+    /// The distance is rounded to whole pixels, so that every base line is on the pixel grid.
+    pub line_spacing: f32,
+
+    /// Maximum width of the lines, in EMs. The default is `None`.
+    ///
+    /// With `WhiteSpace::Preserve`, the lines that are longer are truncated and an ellipsis is
+    /// inserted in place of the characters that are removed. With the other modes, the lines are
+    /// wrapped after white space, or between two characters for words that are too long.
+    pub max_width: Option<f32>,
+
+    /// Maximum number of lines. The following lines are removed, and an ellipsis is added at the
+    /// end of the last line that is kept unless they are only white space. The default is
+    /// `None`.
+    pub max_lines: Option<usize>,
+
+    /// Which part of the lines longer than `max_width` is removed. The default is
+    /// `Truncation::End`.
+    pub truncation: Truncation,
+
+    /// Positions of the tabulations. The default is `TabStops::Spaces(8)`.
+    pub tab_stops: TabStops,
+
+    /// Handling of the white space and of the line breaks. The default is
+    /// `WhiteSpace::Preserve`.
+    pub white_space: WhiteSpace,
+
+    /// Places the grapheme clusters on a grid of cells of `FontTexture::cell_width`, like a
+    /// terminal does. The wide characters of East Asian scripts span two cells, and the text is
+    /// always laid out from left to right. The default is `false`.
+    pub grid: bool,
+
+    /// Direction of the lines. The default is `WritingMode::Horizontal`.
+    pub writing_mode: WritingMode,
+
+    /// Size at which the text is displayed, in pixels per EM. The glyphs come from the smallest
+    /// of `FontTexture::pixel_sizes` that is at least as large, or from the largest one, and
+    /// are placed on the pixels of this size. `TextDisplay::pixel_matrix` and `draw_pixels`
+    /// scale the text to it. The default is `None`, which is the `font_size` of the texture.
+    pub pixel_size: Option<f32>,
+
+    /// OpenType features to enable or disable, for example `*b"tnum"` for digits that all have
+    /// the same width, `*b"smcp"` for small capitals or the stylistic sets `*b"ss01"` to
+    /// `*b"ss20"`. When several entries have the same tag, the last one wins. The default is
+    /// empty.
+    ///
+    /// A feature can only be enabled if it is in `FontOptions::features`, and only the features
+    /// that replace a glyph with another one have an effect ; ligatures are never formed. The
+    /// vertical alternates of `vert` are enabled unless it is disabled here.
+    pub features: Vec<Feature>,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> LayoutOptions {
+        LayoutOptions {
+            line_spacing: 1.0,
+            max_width: None,
+            max_lines: None,
+            truncation: Truncation::End,
+            tab_stops: TabStops::Spaces(8),
+            white_space: WhiteSpace::Preserve,
+            grid: false,
+            writing_mode: WritingMode::Horizontal,
+            pixel_size: None,
+            features: Vec::new(),
+        }
+    }
+}
+
+/// An OpenType feature of `LayoutOptions::features`.
+///
+/// Only the single substitutions of a feature are applied, from its lookups of type 1 or
+/// extensions of them. The ligatures (type 4) and the contextual lookups are ignored, so that
+/// features such as `liga` or `calt` have no effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Feature {
+    /// Tag of the feature, for example `*b"tnum"`.
+    pub tag: [u8; 4],
+
+    /// Enables the feature if true, and disables it otherwise.
+    pub enabled: bool,
+}
+
+/// Positions of the tabulations of a line. A `\t` moves the pen to the next tab stop.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TabStops {
+    /// A tab stop every given number of spaces.
+    Spaces(u32),
+
+    /// Tab stops at the given distances from the start of the line, in EMs and in increasing
+    /// order. After the last one, a `\t` moves the pen by the width of a space.
+    Positions(Vec<f32>),
+}
+
+/// Handling of the white space of a text, similar to the CSS `white-space` property.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WhiteSpace {
+    /// The white space is kept, and the lines only end at the line breaks of the text.
+    Preserve,
+
+    /// Each sequence of white space, including the line breaks, is displayed as a single space,
+    /// and the white space at the start and end of the text is removed. The lines are wrapped
+    /// at `LayoutOptions::max_width`.
+    Collapse,
+
+    /// The white space and the line breaks are kept, and the lines are also wrapped at
+    /// `LayoutOptions::max_width`.
+    PreWrap,
+}
+
+/// Direction of the lines of a text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WritingMode {
+    /// Lines from left to right, stacked from top to bottom.
+    Horizontal,
+
+    /// Columns from top to bottom, stacked from right to left, like Japanese and Chinese texts.
+    /// The characters of East Asian scripts are upright and use the vertical metrics and
+    /// alternates of the font, while the other scripts are rotated sideways.
+    ///
+    /// The text starts at the top-right corner of the first column, so it extends to the left of
+    /// the origin and below it. The lines are the columns, `LayoutOptions::max_width` limits
+    /// their length, and `LayoutOptions::grid` is ignored.
+    VerticalRl,
+}
+
+/// Part of a line that is replaced with an ellipsis when the line is too long.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    /// Keeps the start of the line.
+    End,
+    /// Keeps the end of the line.
+    Start,
+    /// Keeps the start and the end of the line, which suits file paths.
+    Middle,
+}
+
+/// Dimensions of a text, in EMs.
+///
+/// With `WritingMode::VerticalRl`, `width` is the width of all the columns, `height` is the
+/// length of the longest column, `ascent` is zero, and the lines are the columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextMetrics {
+    /// Width of the longest line.
+    pub width: f32,
+
+    /// Distance between the ascender of the first line and the descender of the last one.
+    pub height: f32,
+
+    /// Height of the ascender above the base line of the first line.
+    pub ascent: f32,
+
+    /// Position of the descender of the last line, relative to the base line of the first line.
+    /// Negative.
+    pub descent: f32,
+
+    /// Number of lines. Each `\n` starts a new line.
+    pub line_count: usize,
+
+    /// Width of each line.
+    pub line_widths: Vec<f32>,
+
+    /// Width of each column with `WritingMode::VerticalRl`, from right to left. Empty for
+    /// horizontal texts.
+    pub column_widths: Vec<f32>,
+
+    /// Byte offsets of the boundaries between the grapheme clusters of the text, in increasing
+    /// order. Starts with `0` and ends with the length of the text.
+    ///
+    /// A caret must only be placed at one of these offsets, for example when moving it with the
+    /// arrow keys.
+    pub cluster_boundaries: Vec<usize>,
+
+    /// Byte ranges of the text that are displayed, in increasing order. This is the whole text,
+    /// unless it was truncated because of `LayoutOptions::max_width` or
+    /// `LayoutOptions::max_lines`.
+    pub visible_ranges: Vec<Range<usize>>,
+}
+
+/// A rectangle, in EMs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    /// Position of the left edge.
+    pub left: f32,
+    /// Position of the bottom edge.
+    pub bottom: f32,
+    /// Width of the rectangle.
+    pub width: f32,
+    /// Height of the rectangle.
+    pub height: f32,
+}
+
+/// The metrics of the glyphs of a font that the layout needs.
+pub(crate) trait GlyphMetrics {
+    /// Returns the metrics of the font, in EMs.
+    fn metrics(&self) -> FontMetrics;
+
+    /// Returns the size of the text when `LayoutOptions::pixel_size` is `None`, in pixels per EM.
+    fn em_pixels(&self) -> f32;
+
+    /// Returns the number of horizontal positions at which each glyph is available.
+    fn subpixel_positions(&self) -> u32;
+
+    /// Returns the size of the glyphs to use for a text displayed at a size, which is the
+    /// smallest one that is at least as large, so that the glyphs are scaled down.
+    fn nearest_size(&self, pixel_size: f32) -> u32;
+
+    /// Returns the infos of a character at a size, for the given horizontal position.
+    fn character_infos(&self, character: char, size: u32, phase: u32) -> Option<CharacterInfos>;
+
+    /// Returns the infos of the glyph that replaces a character when a feature is enabled, if
+    /// the feature replaces it.
+    fn alternate_infos(&self, character: char, size: u32, phase: u32, feature: &[u8; 4])
+                       -> Option<CharacterInfos>;
+
+    /// Returns the position of the origin of a mark relative to the origin of the base it is
+    /// attached to, if the font has anchors for them.
+    fn mark_offset(&self, base: char, mark: char) -> Option<(f32, f32)>;
+}
+
+/// A glyph with its position.
+pub(crate) struct PositionedGlyph {
+    pub(crate) infos: CharacterInfos,
+
+    // position of the origin of the glyph, in EMs from the start of the base line of the first
+    // line
+    pub(crate) origin: (f32, f32),
+
+    // index of the cluster of the glyph in `Layout::clusters`
+    pub(crate) cluster: usize,
+
+    // true if the glyph is rotated clockwise, in a vertical text
+    pub(crate) sideways: bool,
+}
+
+/// A grapheme cluster with its position.
+pub(crate) struct Cluster {
+    // bytes of the text covered by the cluster
+    pub(crate) range: Range<usize>,
+
+    // position of the left edge of the cluster and its width, in EMs ; in a vertical text, the
+    // distance between the top of the column and the top of the cluster, and its height
+    pub(crate) x: f32,
+    pub(crate) advance: f32,
+
+    // true if the cluster is part of a right-to-left run
+    pub(crate) rtl: bool,
+}
+
+/// A line of the text.
+pub(crate) struct Line {
+    // bytes of the text covered by the line, without the line break
+    pub(crate) range: Range<usize>,
+
+    // indices of the clusters of the line in `Layout::clusters`, which are in visual order
+    pub(crate) clusters: Range<usize>,
+
+    // the center of the column in a vertical text
+    pub(crate) base_line: f32,
+}
+
+/// The result of the layout of a text.
+pub(crate) struct Layout {
+    pub(crate) glyphs: Vec<PositionedGlyph>,
+    pub(crate) clusters: Vec<Cluster>,
+    pub(crate) lines: Vec<Line>,
+    pub(crate) metrics: TextMetrics,
+
+    // copied from the font, for the carets and the backgrounds
+    pub(crate) line_height: f32,
+    pub(crate) ascender: f32,
+    pub(crate) descender: f32,
+
+    pub(crate) vertical: bool,
+}
+
+impl Layout {
+    /// Returns the offset in the text of the caret closest to a point.
+    pub(crate) fn hit_test(&self, point: (f32, f32)) -> usize {
+        // the distance from the start of the first line across the lines, and the position
+        // along them
+        let (across, x) = if self.vertical {
+            (-point.0, -point.1)
+        } else {
+            (self.ascender - point.1, point.0)
+        };
+
+        // each line owns the space between its top and the top of the next line
+        let line = (across / self.line_height).floor();
+        let line = if line < 0.0 { 0 } else { (line as usize).min(self.lines.len() - 1) };
+        let line = &self.lines[line];
+
+        let clusters = &self.clusters[line.clusters.clone()];
+        let (first, last) = match (clusters.first(), clusters.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return line.range.start,
+        };
+
+        // the offset of the left and right edges of a cluster depends on its direction
+        let left_edge = |c: &Cluster| if c.rtl { c.range.end } else { c.range.start };
+        let right_edge = |c: &Cluster| if c.rtl { c.range.start } else { c.range.end };
+
+        if x < first.x {
+            return left_edge(first);
+        }
+
+        for cluster in clusters {
+            if x < cluster.x + cluster.advance {
+                return if x < cluster.x + cluster.advance / 2.0 {
+                    left_edge(cluster)
+                } else {
+                    right_edge(cluster)
+                };
+            }
+        }
+
+        right_edge(last)
+    }
+
+    /// Returns the position of the bottom of the caret placed before the byte at `offset`, and
+    /// its height. In a vertical text, the caret is horizontal and this is its left end and its
+    /// width.
+    pub(crate) fn caret_position(&self, offset: usize) -> (f32, f32, f32) {
+        let line = self.lines.iter().rev().find(|l| l.range.start <= offset)
+                                          .unwrap_or(&self.lines[0]);
+        let offset = offset.min(line.range.end);
+
+        // the caret is on the leading edge of the cluster that contains the offset, or on the
+        // trailing edge of the cluster that ends there if the offset is the end of the line
+        let mut x = 0.0;
+        for cluster in &self.clusters[line.clusters.clone()] {
+            if cluster.range.start <= offset && offset < cluster.range.end {
+                x = if cluster.rtl { cluster.x + cluster.advance } else { cluster.x };
+                break;
+            }
+            if cluster.range.end == offset {
+                x = if cluster.rtl { cluster.x } else { cluster.x + cluster.advance };
+            }
+        }
+
+        let rect = self.line_rect(line, x, x, false);
+        if self.vertical {
+            (rect.left, rect.bottom, rect.width)
+        } else {
+            (rect.left, rect.bottom, rect.height)
+        }
+    }
+
+    /// Returns the rectangles covered by the clusters of a range of bytes of the text.
+    pub(crate) fn selection_rects(&self, range: Range<usize>) -> Vec<Rect> {
+        let mut rects = Vec::new();
+
+        for line in &self.lines {
+            // start and end of the last rectangle of the line
+            let mut current: Option<(f32, f32)> = None;
+
+            for cluster in &self.clusters[line.clusters.clone()] {
+                if cluster.range.end <= range.start || range.end <= cluster.range.start {
+                    continue;
+                }
+
+                // clusters that touch on the screen are merged, so that a bidirectional text
+                // gives one rectangle per visual run
+                current = match current {
+                    Some((start, end)) if (end - cluster.x).abs() < 1e-4 => {
+                        Some((start, end + cluster.advance))
+                    },
+                    Some((start, end)) => {
+                        rects.push(self.line_rect(line, start, end, false));
+                        Some((cluster.x, cluster.x + cluster.advance))
+                    },
+                    None => Some((cluster.x, cluster.x + cluster.advance)),
+                };
+            }
+
+            if let Some((start, end)) = current {
+                rects.push(self.line_rect(line, start, end, false));
+            }
+        }
+
+        rects
+    }
+
+    /// Returns the cell of each cluster for the colors of `TextDisplay::set_text_with_colors`,
+    /// which is the index of its first grapheme cluster in `text` without the line breaks.
+    pub(crate) fn cells(&self, text: &str) -> Vec<Option<usize>> {
+        let cluster_starts: Vec<usize> = text.grapheme_indices(true)
+                                             .filter(|&(_, g)| g != "\n" && g != "\r\n")
+                                             .map(|(offset, _)| offset)
+                                             .collect();
+
+        self.clusters.iter()
+            .map(|cluster| cluster_starts.binary_search(&cluster.range.start).ok())
+            .collect()
+    }
+
+    /// Returns the rectangle of a line between two positions along it. With `gapless`, the
+    /// rectangle has the full height of the line, so that the rectangles of consecutive lines
+    /// touch ; otherwise it spans from the ascender to the descender.
+    pub(crate) fn line_rect(&self, line: &Line, start: f32, end: f32, gapless: bool) -> Rect {
+        // the edges across the line, in the direction of the next lines
+        let (before, after) = if gapless {
+            (self.ascender, self.ascender - self.line_height)
+        } else {
+            (self.ascender, self.descender)
+        };
+
+        if self.vertical {
+            // the columns are centered on their base line, and the ascender is on the right
+            let right = line.base_line + (self.ascender - self.descender) / 2.0;
+            Rect {
+                left: right - (before - after),
+                bottom: -end,
+                width: before - after,
+                height: end - start,
+            }
+        } else {
+            Rect {
+                left: start,
+                bottom: line.base_line + after,
+                width: end - start,
+                height: before - after,
+            }
+        }
+    }
+}
+
+/// Positions the glyphs of a text. Characters that are not in the font are ignored.
+pub(crate) fn layout(font: &dyn GlyphMetrics, text: &str, options: &LayoutOptions) -> Layout {
+    let font_metrics = font.metrics();
+    let line_height = round_to_pixel(font, options,
+                                     font_metrics.line_height() * options.line_spacing);
+    let vertical = options.writing_mode == WritingMode::VerticalRl;
+    let max_width = options.max_width.unwrap_or(::std::f32::INFINITY);
+    let max_lines = options.max_lines.unwrap_or(::std::usize::MAX).max(1);
+
+    // the ellipsis, or three periods if the font doesn't have it
+    let ellipsis = if glyph_infos(font, options, '\u{2026}', 0, false).is_some() {
+        "\u{2026}"
+    } else {
+        "..."
+    };
+    let ellipsis_width = measure(font, options, ellipsis);
+
+    // the lines of the text before wrapping, with the offset in the text of each of their bytes
+    // and of their end
+    let paragraphs = match options.white_space {
+        WhiteSpace::Collapse => vec![collapse_white_space(text)],
+        WhiteSpace::Preserve | WhiteSpace::PreWrap => {
+            let mut paragraphs = Vec::new();
+            let mut line_start = 0;
+            for line in text.split('\n') {
+                let line_offset = line_start;
+                line_start += line.len() + 1;
+
+                let line = if line.ends_with('\r') { &line[.. line.len() - 1] } else { line };
+                let map = (line_offset .. line_offset + line.len() + 1).collect();
+                paragraphs.push((line.to_owned(), map));
+            }
+            paragraphs
+        },
+    };
+
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut clusters = Vec::with_capacity(text.len());
+    let mut lines = Vec::new();
+    let mut line_widths = Vec::new();
+    let mut hidden = Vec::new();
+    let paragraphs_count = paragraphs.len();
+
+    'paragraphs: for (paragraph_index, (paragraph, map)) in paragraphs.into_iter().enumerate() {
+        let segments = match options.white_space {
+            WhiteSpace::Preserve => vec![0 .. paragraph.len()],
+            WhiteSpace::Collapse | WhiteSpace::PreWrap => wrap(font, options, &paragraph,
+                                                                max_width),
+        };
+        let segments_count = segments.len();
+
+        for (segment_index, segment) in segments.into_iter().enumerate() {
+            let line = &paragraph[segment.clone()];
+            let map = &map[segment.start .. segment.end + 1];
+            let base_line = if vertical {
+                -(lines.len() as f32) * line_height -
+                    (font_metrics.ascender - font_metrics.descender) / 2.0
+            } else {
+                // the first base line is at 0, and the following ones are whole pixels below
+                round_to_pixel(font, options, -(lines.len() as f32) * line_height)
+            };
+            let first_cluster = clusters.len();
+
+            // when the following lines are removed, the ellipsis goes at the end of the last line,
+            // unless they are only white space like a final line break
+            let last_line = lines.len() + 1 == max_lines &&
+                            (paragraph_index + 1 < paragraphs_count ||
+                             segment_index + 1 < segments_count);
+            let more_lines = last_line &&
+                             text[map[line.len()] ..].chars().any(|c| !c.is_whitespace());
+
+            // wrapped lines are only truncated because of `max_lines`
+            let width = measure(font, options, line);
+            let overflow = options.white_space == WhiteSpace::Preserve && width > max_width;
+
+            let pen = if overflow || more_lines {
+                let truncation = if overflow { options.truncation } else { Truncation::End };
+                let (head, tail) = truncate(font, options, line, max_width - ellipsis_width,
+                                            truncation);
+                if head != tail {
+                    hidden.push(map[head] .. map[tail]);
+                }
+
+                // the ellipsis stands for the characters it replaces
+                let shown = format!("{}{}{}", &line[.. head], ellipsis, &line[tail ..]);
+                let mut shown_map = map[.. head].to_vec();
+                shown_map.extend(ellipsis.bytes().map(|_| map[head]));
+                shown_map.extend_from_slice(&map[tail ..]);
+
+                layout_line(font, options, &shown, &shown_map, base_line, &mut glyphs,
+                            &mut clusters)
+            } else {
+                layout_line(font, options, line, map, base_line, &mut glyphs, &mut clusters)
+            };
+
+            lines.push(Line {
+                range: map[0] .. map[line.len()],
+                clusters: first_cluster .. clusters.len(),
+                base_line: base_line,
+            });
+            line_widths.push(pen);
+
+            if last_line {
+                hidden.push(map[line.len()] .. text.len());
+                break 'paragraphs;
+            }
+        }
+    }
+
+    // the parts of the text between the hidden ranges
+    let mut visible_ranges = Vec::new();
+    let mut visible_start = 0;
+    for range in hidden {
+        if range.start > visible_start {
+            visible_ranges.push(visible_start .. range.start);
+        }
+        visible_start = range.end;
+    }
+    if visible_start < text.len() || visible_ranges.is_empty() {
+        visible_ranges.push(visible_start .. text.len());
+    }
+
+    // the line breaks are clusters that aren't part of any line
+    let mut cluster_boundaries: Vec<usize> = clusters.iter().map(|c| c.range.start)
+                                                     .chain(lines.iter().map(|l| l.range.start))
+                                                     .chain(lines.iter().map(|l| l.range.end))
+                                                     .chain(Some(text.len()))
+                                                     .collect();
+    cluster_boundaries.sort();
+    cluster_boundaries.dedup();
+
+    let line_count = line_widths.len();
+    let longest = line_widths.iter().fold(0.0, |a, &b| if b > a { b } else { a });
+
+    let metrics = if vertical {
+        let column_width = font_metrics.ascender - font_metrics.descender;
+        TextMetrics {
+            width: (line_count - 1) as f32 * line_height + column_width,
+            height: longest,
+            ascent: 0.0,
+            descent: -longest,
+            line_count: line_count,
+            line_widths: line_widths,
+            column_widths: vec![column_width; line_count],
+            cluster_boundaries: cluster_boundaries,
+            visible_ranges: visible_ranges,
+        }
+    } else {
+        let descent = font_metrics.descender - (line_count - 1) as f32 * line_height;
+        TextMetrics {
+            width: longest,
+            height: font_metrics.ascender - descent,
+            ascent: font_metrics.ascender,
+            descent: descent,
+            line_count: line_count,
+            line_widths: line_widths,
+            column_widths: Vec::new(),
+            cluster_boundaries: cluster_boundaries,
+            visible_ranges: visible_ranges,
+        }
+    };
+
+    Layout {
+        glyphs: glyphs,
+        clusters: clusters,
+        lines: lines,
+        metrics: metrics,
+        line_height: line_height,
+        ascender: font_metrics.ascender,
+        descender: font_metrics.descender,
+        vertical: vertical,
+    }
+}
+
+/// Positions the glyphs of a line and adds its clusters. `map` contains the offset in the text
+/// of each byte of the line and of its end. Returns the width of the line.
+fn layout_line(font: &dyn GlyphMetrics, options: &LayoutOptions, line: &str, map: &[usize],
+               base_line: f32, glyphs: &mut Vec<PositionedGlyph>, clusters: &mut Vec<Cluster>)
+               -> f32
+{
+    if options.writing_mode == WritingMode::VerticalRl {
+        return layout_column(font, options, line, map, base_line, glyphs, clusters);
+    }
+
+    let mut pen = 0.0;
+
+    // the runs of the line, in visual order
+    let mut runs = Vec::new();
+    if options.grid {
+        runs.push((false, 0 .. line.len()));
+    } else {
+        let bidi = BidiInfo::new(line, None);
+        for paragraph in &bidi.paragraphs {
+            let (levels, paragraph_runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+            for run in paragraph_runs {
+                runs.push((levels[run.start].is_rtl(), run));
+            }
+        }
+    }
+
+    for (rtl, run) in runs {
+        let mut graphemes: Vec<_> = line[run.clone()].grapheme_indices(true).collect();
+        if rtl {
+            graphemes.reverse();
+        }
+
+        for (grapheme_offset, grapheme) in graphemes {
+            let cluster_start = pen;
+
+            if grapheme == "\t" {
+                pen += tab_advance(font, options, pen);
+            }
+
+            // on a grid, the base is centered in its cells
+            let cells_width = if options.grid { cluster_advance(font, options, grapheme, pen) }
+                              else { 0.0 };
+
+            // the first character of the cluster that is in the font, and its position
+            let mut base = None;
+
+            for character in grapheme.chars().filter(|&c| c != '\t') {
+                // combining marks that have an anchor on the base are placed on it, without
+                // moving the pen ; the others follow the base like any character
+                let attachment = base.and_then(|(base, base_x)| {
+                    font.mark_offset(base, character).map(|(x, y)| (base_x + x, y))
+                });
+                let (x, y) = match attachment {
+                    Some(position) => position,
+                    None if options.grid && base.is_none() => {
+                        let infos = glyph_infos(font, options, character, 0, false);
+                        let advance = infos.map_or(0.0, |infos| {
+                            infos.left_padding + infos.size.0 + infos.right_padding
+                        });
+                        (pen + (cells_width - advance) / 2.0, 0.0)
+                    },
+                    None => (pen, 0.0),
+                };
+
+                let (origin, phase) = snap(font, options, x);
+                let y = round_to_pixel(font, options, y);
+
+                let infos = match glyph_infos(font, options, character, phase, false) {
+                    Some(infos) => infos,
+                    None => continue        // character not found in the font, ignoring it
+                };
+
+                glyphs.push(PositionedGlyph {
+                    infos: infos,
+                    origin: (origin, base_line + y),
+                    cluster: clusters.len(),
+                    sideways: false,
+                });
+
+                if attachment.is_none() {
+                    if base.is_none() {
+                        base = Some((character, x));
+                    }
+
+                    // going to next char
+                    pen += infos.left_padding + infos.size.0 + infos.right_padding;
+                }
+            }
+
+            if options.grid && grapheme != "\t" {
+                pen = cluster_start + cells_width;
+            }
+
+            let start = run.start + grapheme_offset;
+            clusters.push(Cluster {
+                range: map[start] .. map[start + grapheme.len()],
+                x: cluster_start,
+                advance: pen - cluster_start,
+                rtl: rtl,
+            });
+        }
+    }
+
+    pen
+}
+
+/// Positions the glyphs of a column of a vertical text, whose center is at `center`, and adds
+/// its clusters. Same as `layout_line` otherwise. Returns the length of the column.
+fn layout_column(font: &dyn GlyphMetrics, options: &LayoutOptions, line: &str, map: &[usize],
+                 center: f32, glyphs: &mut Vec<PositionedGlyph>, clusters: &mut Vec<Cluster>)
+                 -> f32
+{
+    let font_metrics = font.metrics();
+
+    // the base line of the sideways glyphs, which puts the middle of the ascender and the
+    // descender on the center of the column
+    let base_line = round_to_pixel(font, options, center - (font_metrics.ascender +
+                                                            font_metrics.descender) / 2.0);
+
+    let mut pen = 0.0;
+
+    for (grapheme_offset, grapheme) in line.grapheme_indices(true) {
+        let cluster_start = pen;
+        let upright = is_upright(grapheme);
+
+        if grapheme == "\t" {
+            pen += tab_advance(font, options, pen);
+        }
+
+        // the first character of the cluster that is in the font, and its position in the
+        // frame of the glyphs ; this frame is the screen for the upright glyphs, and it is
+        // rotated clockwise with `x` going down the column for the sideways ones
+        let mut base = None;
+
+        for character in grapheme.chars().filter(|&c| c != '\t') {
+            let attachment = base.and_then(|(base, base_x, base_y)| {
+                font.mark_offset(base, character).map(|(x, y)| (base_x + x, base_y + y))
+            });
+
+            let (x, y) = match attachment {
+                Some(position) => position,
+                None if upright => {
+                    let infos = match glyph_infos(font, options, character, 0, true) {
+                        Some(infos) => infos,
+                        None => continue
+                    };
+                    let advance = infos.left_padding + infos.size.0 + infos.right_padding;
+                    (center - advance / 2.0, -pen - infos.vertical_origin)
+                },
+                None => (pen, 0.0),
+            };
+
+            let (origin, phase) = snap(font, options, x);
+            let y = round_to_pixel(font, options, y);
+
+            let infos = match glyph_infos(font, options, character, phase, upright) {
+                Some(infos) => infos,
+                None => continue        // character not found in the font, ignoring it
+            };
+
+            glyphs.push(PositionedGlyph {
+                infos: infos,
+                origin: if upright { (origin, y) } else { (base_line + y, -origin) },
+                cluster: clusters.len(),
+                sideways: !upright,
+            });
+
+            if attachment.is_none() {
+                if base.is_none() {
+                    base = Some((character, x, y));
+                }
+
+                pen += if upright {
+                    infos.vertical_advance
+                } else {
+                    infos.left_padding + infos.size.0 + infos.right_padding
+                };
+            }
+        }
+
+        clusters.push(Cluster {
+            range: map[grapheme_offset] .. map[grapheme_offset + grapheme.len()],
+            x: cluster_start,
+            advance: pen - cluster_start,
+            rtl: false,
+        });
+    }
+
+    pen
+}
+
+/// Returns the infos of the glyph of a character, with the features of the options. `upright`
+/// enables the vertical alternates.
+fn glyph_infos(font: &dyn GlyphMetrics, options: &LayoutOptions, character: char, phase: u32,
+               upright: bool) -> Option<CharacterInfos>
+{
+    let features = &options.features;
+    let size = font.nearest_size(pixel_size(font, options));
+
+    // the last entry of each tag decides if the feature is enabled, and the features that come
+    // later in the list take precedence
+    for (index, feature) in features.iter().enumerate().rev() {
+        if !feature.enabled || feature.tag == *b"vert" ||
+           features[index + 1 ..].iter().any(|f| f.tag == feature.tag)
+        {
+            continue;
+        }
+        if let Some(infos) = font.alternate_infos(character, size, phase, &feature.tag) {
+            return Some(infos);
+        }
+    }
+
+    let vertical = features.iter().rev().find(|f| f.tag == *b"vert").map_or(true, |f| f.enabled);
+    if upright && vertical {
+        if let Some(infos) = font.alternate_infos(character, size, phase, b"vert") {
+            return Some(infos);
+        }
+    }
+
+    font.character_infos(character, size, phase)
+}
+
+/// Returns the number of pixels per EM of a text.
+fn pixel_size(font: &dyn GlyphMetrics, options: &LayoutOptions) -> f32 {
+    options.pixel_size.unwrap_or(font.em_pixels())
+}
+
+/// Returns the width of the cells of `LayoutOptions::grid`.
+pub(crate) fn cell_width(font: &dyn GlyphMetrics, options: &LayoutOptions) -> f32 {
+    let advance = glyph_infos(font, options, '0', 0, false)
+                      .or_else(|| glyph_infos(font, options, ' ', 0, false))
+                      .map_or(0.5, |infos| infos.left_padding + infos.size.0 +
+                                           infos.right_padding);
+
+    let em_pixels = pixel_size(font, options);
+    (advance * em_pixels).round().max(1.0) / em_pixels
+}
+
+/// Returns true if a grapheme cluster stays upright in a vertical text, which is the case of
+/// the wide characters of East Asian scripts.
+fn is_upright(grapheme: &str) -> bool {
+    grapheme.width() >= 2
+}
+
+/// Returns the position of the origin of a glyph whose ideal position is `x`, and the phase of
+/// the glyph to use.
+fn snap(font: &dyn GlyphMetrics, options: &LayoutOptions, x: f32) -> (f32, u32) {
+    // the glyph is placed on the pixel of the texture at the left of `x`, and we pick the
+    // version of the glyph that is offset by the rest ; without subpixel positioning, this
+    // rounds `x` to the nearest pixel so that the quads are aligned on the pixels of the
+    // texture
+    let positions = font.subpixel_positions();
+    let em_pixels = pixel_size(font, options);
+    let x_pixels = x * em_pixels;
+    let pixel = x_pixels.floor();
+    let phase = ((x_pixels - pixel) * positions as f32).round() as u32;
+    if phase == positions {
+        ((pixel + 1.0) / em_pixels, 0)
+    } else {
+        (pixel / em_pixels, phase)
+    }
+}
+
+/// Rounds a position to the nearest pixel of the texture.
+fn round_to_pixel(font: &dyn GlyphMetrics, options: &LayoutOptions, y: f32) -> f32 {
+    let em_pixels = pixel_size(font, options);
+    (y * em_pixels).round() / em_pixels
+}
+
+/// Returns the width of a grapheme cluster whose left edge is at `pen`, with the same rules as
+/// `layout_line`. In a vertical text, returns its height.
+fn cluster_advance(font: &dyn GlyphMetrics, options: &LayoutOptions, grapheme: &str, pen: f32) -> f32 {
+    if grapheme == "\t" {
+        return tab_advance(font, options, pen);
+    }
+
+    let vertical = options.writing_mode == WritingMode::VerticalRl;
+    if options.grid && !vertical {
+        let cells = grapheme.width().max(1).min(2);
+        return cells as f32 * cell_width(font, options);
+    }
+
+    // the upright clusters of a vertical text use the vertical advances
+    let upright = vertical && is_upright(grapheme);
+    let mut advance = 0.0;
+    let mut base = None;
+
+    for character in grapheme.chars() {
+        if let Some(base) = base {
+            if font.mark_offset(base, character).is_some() {
+                continue;
+            }
+        }
+
+        if let Some(infos) = glyph_infos(font, options, character, 0, upright) {
+            if base.is_none() {
+                base = Some(character);
+            }
+            advance += if upright {
+                infos.vertical_advance
+            } else {
+                infos.left_padding + infos.size.0 + infos.right_padding
+            };
+        }
+    }
+
+    advance
+}
+
+/// Returns the width of a line, ignoring its direction.
+fn measure(font: &dyn GlyphMetrics, options: &LayoutOptions, line: &str) -> f32 {
+    line.graphemes(true).fold(0.0, |pen, g| pen + cluster_advance(font, options, g, pen))
+}
+
+/// Returns the distance between `pen` and the next tab stop.
+fn tab_advance(font: &dyn GlyphMetrics, options: &LayoutOptions, pen: f32) -> f32 {
+    // the width of a space, or a quarter of EM if the font has none
+    let grid = options.grid && options.writing_mode == WritingMode::Horizontal;
+    let space = if grid {
+        cell_width(font, options)
+    } else {
+        glyph_infos(font, options, ' ', 0, false)
+            .map_or(0.25, |infos| infos.left_padding + infos.size.0 + infos.right_padding)
+    };
+
+    let next_stop = match options.tab_stops {
+        TabStops::Spaces(spaces) if spaces > 0 && space > 0.0 => {
+            let interval = spaces as f32 * space;
+            ((pen + 1e-4) / interval).floor() * interval + interval
+        },
+        TabStops::Spaces(_) => pen + space,
+        TabStops::Positions(ref positions) => {
+            positions.iter().cloned().find(|&p| p > pen + 1e-4).unwrap_or(pen + space)
+        },
+    };
+
+    // on a grid, the tab stops are moved to the start of the next cell
+    let next_stop = if grid {
+        ((next_stop - 1e-4) / space).ceil() * space
+    } else {
+        next_stop
+    };
+
+    next_stop - pen
+}
+
+/// Replaces each sequence of white space of a text with a single space, and removes the white
+/// space at its start and end. Returns the new text with the offset of each of its bytes in the
+/// original text, and of its end.
+fn collapse_white_space(text: &str) -> (String, Vec<usize>) {
+    let mut result = String::with_capacity(text.len());
+    let mut map = Vec::with_capacity(text.len() + 1);
+
+    // offset of the current sequence of white space, if it isn't at the start of the text
+    let mut white_space = None;
+
+    for (offset, character) in text.char_indices() {
+        if is_collapsible(character) {
+            if !result.is_empty() && white_space.is_none() {
+                white_space = Some(offset);
+            }
+            continue;
+        }
+
+        if let Some(white_space) = white_space.take() {
+            result.push(' ');
+            map.push(white_space);
+        }
+
+        result.push(character);
+        for _ in 0 .. character.len_utf8() {
+            map.push(offset);
+        }
+    }
+
+    // the end of the text is the end of its last character that is displayed
+    map.push(match white_space {
+        Some(white_space) => white_space,
+        None => text.len(),
+    });
+
+    (result, map)
+}
+
+// the white space that CSS collapses
+fn is_collapsible(character: char) -> bool {
+    match character {
+        ' ' | '\t' | '\n' | '\r' | '\u{c}' => true,
+        _ => false,
+    }
+}
+
+/// Splits a line so that the parts fit in `max_width`. Returns the byte ranges of the parts.
+///
+/// The lines are wrapped after white space, which can go beyond `max_width`, or between two
+/// grapheme clusters if a word doesn't fit on a line.
+fn wrap(font: &dyn GlyphMetrics, options: &LayoutOptions, line: &str, max_width: f32)
+        -> Vec<Range<usize>>
+{
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut pen = 0.0;
+
+    // the last offset after white space in the current part
+    let mut last_break = None;
+
+    for (offset, grapheme) in line.grapheme_indices(true) {
+        let white_space = grapheme == " " || grapheme == "\t";
+        let mut advance = cluster_advance(font, options, grapheme, pen);
+
+        if !white_space && offset > start && pen + advance > max_width {
+            let end = last_break.unwrap_or(offset);
+            parts.push(start .. end);
+            start = end;
+            last_break = None;
+
+            pen = measure(font, options, &line[start .. offset]);
+            advance = cluster_advance(font, options, grapheme, pen);
+        }
+
+        pen += advance;
+        if white_space {
+            last_break = Some(offset + grapheme.len());
+        }
+    }
+
+    parts.push(start .. line.len());
+    parts
+}
+
+/// Chooses the grapheme clusters of a line that are kept so that their width is at most
+/// `available`. Returns the end of the kept start of the line and the beginning of its kept end,
+/// in bytes.
+fn truncate(font: &dyn GlyphMetrics, options: &LayoutOptions, line: &str, available: f32,
+            truncation: Truncation) -> (usize, usize)
+{
+    // the tabulations are measured as if they were at the start of the line
+    let graphemes: Vec<_> = line.grapheme_indices(true)
+                                .map(|(offset, g)| (offset, cluster_advance(font, options, g, 0.0)))
+                                .collect();
+
+    // the clusters `..head` and `tail..` are kept
+    let (mut head, mut tail) = (0, graphemes.len());
+    let (mut head_width, mut tail_width) = (0.0, 0.0);
+
+    while head < tail {
+        let from_head = match truncation {
+            Truncation::End => true,
+            Truncation::Start => false,
+            Truncation::Middle => head_width <= tail_width,
+        };
+
+        let width = if from_head { graphemes[head].1 } else { graphemes[tail - 1].1 };
+        if head_width + tail_width + width > available {
+            break;
+        }
+
+        if from_head {
+            head += 1;
+            head_width += width;
+        } else {
+            tail -= 1;
+            tail_width += width;
+        }
+    }
+
+    let offset = |index: usize| graphemes.get(index).map_or(line.len(), |&(offset, _)| offset);
+    (offset(head), offset(tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {CharacterInfos, FontMetrics};
+
+    /// A font of 10 pixels per EM that only has metrics. Letters and digits are 0.5 EM wide, the
+    /// Hebrew letters too, spaces are 0.3 EM wide and `中` is 1 EM wide. The combining acute
+    /// accent is attached to any base.
+    struct TestFont {
+        // true if the font has `…`, which is 0.4 EM wide
+        ellipsis: bool,
+    }
+
+    impl GlyphMetrics for TestFont {
+        fn metrics(&self) -> FontMetrics {
+            FontMetrics {
+                ascender: 0.8,
+                descender: -0.2,
+                line_gap: 0.0,
+                cap_height: 0.7,
+                x_height: 0.5,
+                underline_position: -0.1,
+                underline_thickness: 0.05,
+            }
+        }
+
+        fn em_pixels(&self) -> f32 {
+            10.0
+        }
+
+        fn subpixel_positions(&self) -> u32 {
+            1
+        }
+
+        fn nearest_size(&self, _: f32) -> u32 {
+            10
+        }
+
+        fn character_infos(&self, character: char, _: u32, phase: u32)
+                           -> Option<CharacterInfos>
+        {
+            let advance = match character {
+                'a' ..= 'z' | 'A' ..= 'Z' | '0' ..= '9' | '.' | '\u{5d0}' ..= '\u{5ea}' => 0.5,
+                ' ' => 0.3,
+                '\u{2026}' if self.ellipsis => 0.4,
+                '\u{301}' => 0.0,
+                '中' => 1.0,
+                _ => return None
+            };
+
+            Some(CharacterInfos {
+                tex_coords: (0.0, 0.0),
+                tex_size: (0.0, 0.0),
+                size: (advance, 0.7),
+                height_over_line: 0.7,
+                left_padding: 0.0,
+                right_padding: 0.0,
+                colored: false,
+                phase: phase,
+                page: 0,
+                vertical_advance: 1.0,
+                vertical_origin: 0.8,
+                feature: None,
+                pixel_size: 10,
+            })
+        }
+
+        fn alternate_infos(&self, _: char, _: u32, _: u32, _: &[u8; 4])
+                           -> Option<CharacterInfos>
+        {
+            None
+        }
+
+        fn mark_offset(&self, _: char, mark: char) -> Option<(f32, f32)> {
+            if mark == '\u{301}' { Some((0.1, 0.0)) } else { None }
+        }
+    }
+
+    fn layout_text(text: &str, options: &LayoutOptions) -> Layout {
+        layout(&TestFont { ellipsis: true }, text, options)
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-4, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn measure_multiple_lines() {
+        let metrics = layout_text("ab\nabcd\n", &Default::default()).metrics;
+        assert_eq!(metrics.line_count, 3);
+        assert_close(metrics.width, 2.0);
+        assert_close(metrics.line_widths[0], 1.0);
+        assert_close(metrics.line_widths[1], 2.0);
+        assert_close(metrics.line_widths[2], 0.0);
+        assert_close(metrics.ascent, 0.8);
+        assert_close(metrics.descent, -2.2);
+        assert_close(metrics.height, 3.0);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 8]);
+
+        let options = LayoutOptions { line_spacing: 1.5, .. Default::default() };
+        let metrics = layout_text("ab\r\nabcd", &options).metrics;
+        assert_eq!(metrics.line_count, 2);
+        assert_close(metrics.width, 2.0);
+        assert_close(metrics.height, 2.5);
+    }
+
+    #[test]
+    fn measure_max_width() {
+        // wrapped after the space, which stays at the end of the first line
+        let options = LayoutOptions {
+            max_width: Some(2.5),
+            white_space: WhiteSpace::PreWrap,
+            .. Default::default()
+        };
+        let metrics = layout_text("aaaa aaaa", &options).metrics;
+        assert_eq!(metrics.line_count, 2);
+        assert_close(metrics.line_widths[0], 2.3);
+        assert_close(metrics.line_widths[1], 2.0);
+        assert_close(metrics.height, 2.0);
+
+        // truncated with an ellipsis when the white space is preserved
+        let options = LayoutOptions { max_width: Some(2.0), .. Default::default() };
+        let metrics = layout_text("aaaaaaaa", &options).metrics;
+        assert_eq!(metrics.line_count, 1);
+        assert_close(metrics.width, 1.9);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 3]);
+    }
+
+    #[test]
+    fn measure_max_lines() {
+        let options = LayoutOptions { max_lines: Some(2), .. Default::default() };
+        let metrics = layout_text("a\nb\nc", &options).metrics;
+        assert_eq!(metrics.line_count, 2);
+        assert_close(metrics.line_widths[1], 0.9);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 3]);
+
+        let metrics = layout_text("a\nb", &options).metrics;
+        assert_eq!(metrics.line_count, 2);
+        assert_close(metrics.line_widths[1], 0.5);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 3]);
+
+        // there is no ellipsis when the lines that are removed are empty
+        let options = LayoutOptions { max_lines: Some(1), .. Default::default() };
+        for text in &["a\n", "a\n \n\t"] {
+            let layout = layout_text(text, &options);
+            assert_eq!(layout.metrics.line_count, 1);
+            assert_close(layout.metrics.line_widths[0], 0.5);
+            assert_eq!(layout.metrics.visible_ranges, vec![0 .. 1]);
+            assert_eq!(layout.glyphs.len(), 1);
+        }
+
+        // with wrapping, the ellipsis goes at the end of the last line that is kept
+        let options = LayoutOptions {
+            max_width: Some(2.5),
+            max_lines: Some(1),
+            white_space: WhiteSpace::Collapse,
+            .. Default::default()
+        };
+        let metrics = layout_text("aaaa  aaaa", &options).metrics;
+        assert_eq!(metrics.line_count, 1);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 4]);
+    }
+
+    // the point at the middle of the height of the caret
+    fn caret_point(layout: &Layout, offset: usize) -> (f32, f32) {
+        let (x, bottom, height) = layout.caret_position(offset);
+        (x, bottom + height / 2.0)
+    }
+
+    #[test]
+    fn caret_round_trip() {
+        let layout = layout_text("ab\ncd", &Default::default());
+        for &offset in &layout.metrics.cluster_boundaries {
+            assert_eq!(layout.hit_test(caret_point(&layout, offset)), offset);
+        }
+
+        let (x, bottom, height) = layout.caret_position(4);
+        assert_close(x, 0.5);
+        assert_close(bottom, -1.2);
+        assert_close(height, 1.0);
+    }
+
+    #[test]
+    fn caret_end_of_line() {
+        let layout = layout_text("ab\ncd", &Default::default());
+
+        // the end of the first line is before its line break
+        let (x, bottom, _) = layout.caret_position(2);
+        assert_close(x, 1.0);
+        assert_close(bottom, -0.2);
+
+        // points past the ends of the lines are on their edges
+        assert_eq!(layout.hit_test((5.0, 0.3)), 2);
+        assert_eq!(layout.hit_test((-1.0, 0.3)), 0);
+        assert_eq!(layout.hit_test((5.0, -0.7)), 5);
+        assert_eq!(layout.hit_test((5.0, -10.0)), 5);
+        assert_eq!(layout.hit_test((0.0, 10.0)), 0);
+    }
+
+    #[test]
+    fn hit_test_combining_marks() {
+        // the accent is part of the cluster of the `e`, which is 0.5 EM wide
+        let layout = layout_text("e\u{301}x", &Default::default());
+        assert_eq!(layout.metrics.cluster_boundaries, vec![0, 3, 4]);
+        assert_close(layout.metrics.width, 1.0);
+
+        assert_eq!(layout.hit_test((0.2, 0.3)), 0);
+        assert_eq!(layout.hit_test((0.3, 0.3)), 3);
+        assert_eq!(layout.hit_test((0.8, 0.3)), 4);
+        assert_close(layout.caret_position(1).0, 0.0);
+        assert_close(layout.caret_position(3).0, 0.5);
+    }
+
+    #[test]
+    fn hit_test_right_to_left() {
+        // `א` is on the right of `ב`, and the right edge of a right-to-left character is its
+        // start in the text
+        let layout = layout_text("\u{5d0}\u{5d1}", &Default::default());
+        assert_eq!(layout.hit_test((0.9, 0.3)), 0);
+        assert_eq!(layout.hit_test((0.6, 0.3)), 2);
+        assert_eq!(layout.hit_test((0.4, 0.3)), 2);
+        assert_eq!(layout.hit_test((0.1, 0.3)), 4);
+        assert_eq!(layout.hit_test((-1.0, 0.3)), 4);
+
+        assert_close(layout.caret_position(0).0, 1.0);
+        assert_close(layout.caret_position(2).0, 0.5);
+        assert_close(layout.caret_position(4).0, 0.0);
+        for &offset in &layout.metrics.cluster_boundaries {
+            assert_eq!(layout.hit_test(caret_point(&layout, offset)), offset);
+        }
+    }
+
+    fn truncation_options(truncation: Truncation) -> LayoutOptions {
+        LayoutOptions { max_width: Some(2.0), truncation: truncation, .. Default::default() }
+    }
+
+    #[test]
+    fn truncate_end_start_middle() {
+        // 1.6 EM are left for the text next to the ellipsis, which is three letters
+        let font = TestFont { ellipsis: true };
+        let options = truncation_options(Truncation::End);
+        assert_eq!(truncate(&font, &options, "abcdefgh", 1.6, Truncation::End), (3, 8));
+        assert_eq!(truncate(&font, &options, "abcdefgh", 1.6, Truncation::Start), (0, 5));
+        assert_eq!(truncate(&font, &options, "abcdefgh", 1.6, Truncation::Middle), (2, 7));
+        // everything is kept when the text fits
+        assert_eq!(truncate(&font, &options, "abc", 1.6, Truncation::Middle), (2, 2));
+
+        let metrics = layout_text("abcdefgh", &truncation_options(Truncation::End)).metrics;
+        assert_close(metrics.width, 1.9);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 3]);
+
+        let metrics = layout_text("abcdefgh", &truncation_options(Truncation::Start)).metrics;
+        assert_close(metrics.width, 1.9);
+        assert_eq!(metrics.visible_ranges, vec![5 .. 8]);
+
+        let metrics = layout_text("abcdefgh", &truncation_options(Truncation::Middle)).metrics;
+        assert_close(metrics.width, 1.9);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 2, 7 .. 8]);
+    }
+
+    #[test]
+    fn truncate_visible_ranges() {
+        // the lines that fit aren't truncated
+        let options = truncation_options(Truncation::Middle);
+        let metrics = layout_text("abcd\nabcdefgh\nab", &options).metrics;
+        assert_eq!(metrics.line_count, 3);
+        assert_eq!(metrics.line_widths[0], 2.0);
+        assert_eq!(metrics.visible_ranges, vec![0 .. 7, 12 .. 16]);
+
+        // the ellipsis is one cluster that covers the hidden characters
+        let layout = layout_text("abcdefgh", &options);
+        let ranges: Vec<_> = layout.clusters.iter().map(|c| c.range.clone()).collect();
+        assert_eq!(ranges, vec![0 .. 1, 1 .. 2, 2 .. 7, 7 .. 8]);
+    }
+
+    #[test]
+    fn truncate_without_ellipsis_glyph() {
+        // three periods are used instead, which leaves room for one letter
+        let font = TestFont { ellipsis: false };
+        let layout = layout(&font, "abcdefgh", &truncation_options(Truncation::End));
+        assert_close(layout.metrics.width, 2.0);
+        assert_eq!(layout.metrics.visible_ranges, vec![0 .. 1]);
+        assert_eq!(layout.glyphs.len(), 4);
+    }
+
+    #[test]
+    fn tab_advance_stops() {
+        // the tabulation has no glyph, so a space is used, which is 0.3 EM wide
+        let font = TestFont { ellipsis: true };
+        let options = LayoutOptions { tab_stops: TabStops::Spaces(4), .. Default::default() };
+        assert_close(tab_advance(&font, &options, 0.0), 1.2);
+        assert_close(tab_advance(&font, &options, 0.5), 0.7);
+        assert_close(tab_advance(&font, &options, 1.2), 1.2);
+
+        let options = LayoutOptions { tab_stops: TabStops::Spaces(0), .. Default::default() };
+        assert_close(tab_advance(&font, &options, 0.5), 0.3);
+
+        let stops = TabStops::Positions(vec![1.0, 2.0]);
+        let options = LayoutOptions { tab_stops: stops, .. Default::default() };
+        assert_close(tab_advance(&font, &options, 0.0), 1.0);
+        assert_close(tab_advance(&font, &options, 1.5), 0.5);
+
+        // past the last tab stop, the pen moves by a space
+        assert_close(tab_advance(&font, &options, 2.0), 0.3);
+        assert_close(tab_advance(&font, &options, 3.0), 0.3);
+
+        let metrics = layout_text("a\tb", &options).metrics;
+        assert_close(metrics.width, 1.5);
+    }
+
+    #[test]
+    fn collapse_white_space_offsets() {
+        assert_eq!(collapse_white_space("  a \t b\n"), ("a b".to_owned(), vec![2, 3, 6, 7]));
+        assert_eq!(collapse_white_space("ab"), ("ab".to_owned(), vec![0, 1, 2]));
+        assert_eq!(collapse_white_space(" \n "), (String::new(), vec![3]));
+    }
+
+    #[test]
+    fn white_space_modes() {
+        let options = |white_space| LayoutOptions {
+            max_width: Some(1.0),
+            white_space: white_space,
+            .. Default::default()
+        };
+
+        // the lines are truncated instead of wrapped
+        let metrics = layout_text("ab cd\nef", &options(WhiteSpace::Preserve)).metrics;
+        assert_eq!(metrics.line_count, 2);
+
+        // the line break is a space
+        let metrics = layout_text(" ab cd\nef ", &options(WhiteSpace::Collapse)).metrics;
+        assert_eq!(metrics.line_count, 3);
+
+        // the white space at the start of the line is kept
+        let metrics = layout_text(" ab cd\nef ", &options(WhiteSpace::PreWrap)).metrics;
+        assert_eq!(metrics.line_count, 4);
+
+        let options = LayoutOptions { white_space: WhiteSpace::Collapse, .. Default::default() };
+        let metrics = layout_text("a  b\nc", &options).metrics;
+        assert_eq!(metrics.line_count, 1);
+        assert_close(metrics.width, 2.1);
+    }
+
+    #[test]
+    fn wrap_at_spaces_and_in_words() {
+        let font = TestFont { ellipsis: true };
+        let options = LayoutOptions::default();
+        assert_eq!(wrap(&font, &options, "ab cd", 1.0), vec![0 .. 3, 3 .. 5]);
+
+        // the white space can go beyond the maximum width
+        assert_eq!(wrap(&font, &options, "ab   cd", 1.0), vec![0 .. 5, 5 .. 7]);
+
+        // the words that are too long are split between two clusters
+        assert_eq!(wrap(&font, &options, "abcdef", 1.0), vec![0 .. 2, 2 .. 4, 4 .. 6]);
+        assert_eq!(wrap(&font, &options, "ab cdefgh", 1.0), vec![0 .. 3, 3 .. 5, 5 .. 7, 7 .. 9]);
+        assert_eq!(wrap(&font, &options, "ae\u{301}b", 0.5), vec![0 .. 1, 1 .. 4, 4 .. 5]);
+
+        // at least one cluster is put on each line
+        assert_eq!(wrap(&font, &options, "\u{4e2d}a", 0.2), vec![0 .. 3, 3 .. 4]);
+    }
+
+    #[test]
+    fn grid_cells() {
+        // the cells are as wide as `0`, which is 0.5 EM, and the wide characters take two
+        let options = LayoutOptions { grid: true, .. Default::default() };
+        let layout = layout_text("a\u{4e2d} b\u{301}\tc", &options);
+        let positions: Vec<_> = layout.clusters.iter().map(|c| (c.x, c.advance)).collect();
+        assert_eq!(positions, vec![(0.0, 0.5), (0.5, 1.0), (1.5, 0.5), (2.0, 0.5), (2.5, 1.5),
+                                   (4.0, 0.5)]);
+        assert_close(layout.metrics.width, 4.5);
+
+        // the glyphs are centered in their cells, and the marks are attached to their base
+        let origins: Vec<_> = layout.glyphs.iter().map(|g| (g.cluster, g.origin.0)).collect();
+        assert!(origins.contains(&(1, 0.5)));
+        assert!(origins.contains(&(2, 1.6)));
+        assert!(origins.contains(&(3, 2.0)));
+        assert!(origins.contains(&(3, 2.1)));
+
+        // every line starts at the first cell
+        let layout = layout_text("ab\ncd", &options);
+        assert_eq!(layout.clusters[2].x, 0.0);
+        assert_eq!(layout.clusters[3].x, 0.5);
+    }
+
+    #[test]
+    fn cells_of_clusters() {
+        // the line breaks don't take a cell
+        let text = "ab\ncd\r\ne";
+        assert_eq!(layout_text(text, &Default::default()).cells(text),
+                   vec![Some(0), Some(1), Some(2), Some(3), Some(4)]);
+
+        // the clusters are in visual order, and a cluster with a mark is one cell
+        let text = "e\u{301}\u{5d0}\u{5d1}";
+        assert_eq!(layout_text(text, &Default::default()).cells(text),
+                   vec![Some(0), Some(2), Some(1)]);
+
+        // the ellipsis has the cell of the first character it replaces
+        let options = LayoutOptions { max_width: Some(2.0), .. Default::default() };
+        assert_eq!(layout_text("abcdefgh", &options).cells("abcdefgh"),
+                   vec![Some(0), Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn base_lines_on_pixels() {
+        // 1.234 EM are 12.34 pixels, which are rounded to 12
+        let options = LayoutOptions { line_spacing: 1.234, .. Default::default() };
+        let layout = layout_text("a\nb\nc\nd", &options);
+        for (index, line) in layout.lines.iter().enumerate() {
+            assert_eq!(line.base_line * 10.0, -12.0 * index as f32);
+        }
+        for glyph in &layout.glyphs {
+            let y = glyph.origin.1 * 10.0;
+            assert_eq!(y, y.round());
+        }
+        assert_close(layout.metrics.height, 4.6);
+    }
+
+    #[test]
+    fn snap_without_subpixel_positions() {
+        // the font has a single phase, so that the origins are rounded to the nearest pixel
+        let font = TestFont { ellipsis: false };
+        let options = LayoutOptions::default();
+        for &(x, snapped) in &[(0.24, 0.2), (0.25, 0.3), (0.26, 0.3), (-0.26, -0.3), (1.0, 1.0)] {
+            let (origin, phase) = snap(&font, &options, x);
+            assert_close(origin, snapped);
+            assert_eq!(phase, 0);
+        }
+
+        // the pixels are those of the size the text is displayed at
+        let options = LayoutOptions { pixel_size: Some(20.0), .. Default::default() };
+        let (origin, phase) = snap(&font, &options, 0.26);
+        assert_close(origin, 0.25);
+        assert_eq!(phase, 0);
+    }
+
+    // compares rectangles given by their left edge, their bottom and their width ; they all
+    // span from the descender to the ascender
+    fn assert_rects(rects: Vec<Rect>, expected: &[(f32, f32, f32)]) {
+        assert_eq!(rects.len(), expected.len());
+        for (rect, &(left, bottom, width)) in rects.iter().zip(expected) {
+            assert_close(rect.left, left);
+            assert_close(rect.bottom, bottom);
+            assert_close(rect.width, width);
+            assert_close(rect.height, 1.0);
+        }
+    }
+
+    #[test]
+    fn selection_in_one_line() {
+        let layout = layout_text("abcd", &Default::default());
+        assert_rects(layout.selection_rects(1 .. 3), &[(0.5, -0.2, 1.0)]);
+        assert_rects(layout.selection_rects(0 .. 4), &[(0.0, -0.2, 2.0)]);
+        assert_rects(layout.selection_rects(2 .. 2), &[]);
+    }
+
+    #[test]
+    fn selection_across_wrapped_lines() {
+        let options = LayoutOptions {
+            max_width: Some(1.0),
+            white_space: WhiteSpace::Collapse,
+            .. Default::default()
+        };
+        let layout = layout_text("ab cd", &options);
+        assert_rects(layout.selection_rects(1 .. 4), &[(0.5, -0.2, 0.8), (0.0, -1.2, 0.5)]);
+    }
+
+    #[test]
+    fn selection_right_to_left() {
+        // the Hebrew letters are displayed in reverse order between `a` and `b`
+        let layout = layout_text("a\u{5d0}\u{5d1}b", &Default::default());
+        assert_rects(layout.selection_rects(1 .. 5), &[(0.5, -0.2, 1.0)]);
+
+        // the logical range isn't contiguous on the screen
+        assert_rects(layout.selection_rects(0 .. 3), &[(0.0, -0.2, 0.5), (1.0, -0.2, 0.5)]);
+    }
+
+    #[test]
+    fn selection_over_ellipsis() {
+        // the ellipsis is selected with any of the characters it replaces
+        let options = LayoutOptions { max_width: Some(2.0), .. Default::default() };
+        let layout = layout_text("abcdefgh", &options);
+        assert_rects(layout.selection_rects(4 .. 6), &[(1.5, -0.2, 0.4)]);
+        assert_rects(layout.selection_rects(2 .. 8), &[(1.0, -0.2, 0.9)]);
+    }
+
+    fn vertical_text(text: &str) -> Layout {
+        let options = LayoutOptions {
+            writing_mode: WritingMode::VerticalRl,
+            .. Default::default()
+        };
+        layout_text(text, &options)
+    }
+
+    #[test]
+    fn vertical_columns() {
+        // `中` is upright and uses its vertical advance, the letters are sideways
+        let layout = vertical_text("\u{4e2d}ab\n\u{4e2d}");
+        let positions: Vec<_> = layout.clusters.iter().map(|c| (c.x, c.advance)).collect();
+        assert_eq!(positions, vec![(0.0, 1.0), (1.0, 0.5), (1.5, 0.5), (0.0, 1.0)]);
+
+        // the columns go from right to left, and are centered on their base line
+        assert_eq!(layout.lines.len(), 2);
+        assert_close(layout.lines[0].base_line, -0.5);
+        assert_close(layout.lines[1].base_line, -1.5);
+
+        let glyphs: Vec<_> = layout.glyphs.iter().map(|g| (g.origin, g.sideways)).collect();
+        assert_eq!(glyphs[0], ((-1.0, -0.8), false));
+        assert_eq!(glyphs[1], ((-0.8, -1.0), true));
+        assert_eq!(glyphs[3], ((-2.0, -0.8), false));
+
+        assert_close(layout.metrics.width, 2.0);
+        assert_close(layout.metrics.height, 2.0);
+    }
+
+    #[test]
+    fn vertical_hit_test() {
+        let layout = vertical_text("\u{4e2d}ab\n\u{4e2d}");
+
+        // the offsets of the first column are found from the top
+        assert_eq!(layout.hit_test((-0.5, -0.2)), 0);
+        assert_eq!(layout.hit_test((-0.5, -1.2)), 3);
+        assert_eq!(layout.hit_test((-0.5, -1.3)), 4);
+        assert_eq!(layout.hit_test((-0.5, -5.0)), 5);
+
+        // the second column is on the left of the first one
+        assert_eq!(layout.hit_test((-1.5, -0.2)), 6);
+        assert_eq!(layout.hit_test((-1.5, -0.7)), 9);
+
+        // the carets are horizontal, across their column
+        for &(offset, caret) in &[(0, (-1.0, 0.0, 1.0)), (3, (-1.0, -1.0, 1.0)),
+                                  (5, (-1.0, -2.0, 1.0)), (9, (-2.0, -1.0, 1.0))]
+        {
+            let (x, y, width) = layout.caret_position(offset);
+            assert_close(x, caret.0);
+            assert_close(y, caret.1);
+            assert_close(width, caret.2);
+            assert_eq!(layout.hit_test((x + width / 2.0, y)), offset);
+        }
+    }
+}
@@ -14,8 +14,8 @@ Usage:
 let system = glium_text::TextSystem::new(&display);
 
 // Creating a `FontTexture`, which a regular `Texture` which contains the font.
-// Note that loading the systems fonts is not covered by this library.
-let font = glium_text::FontTexture::new(&display, std::fs::File::open(&std::path::Path::new("my_font.ttf")).unwrap(), 24).unwrap();
+// The `system_fonts` module can be used to find the fonts installed on the system.
+let font = glium_text::FontTexture::from_file(&display, "my_font.ttf", 24, &Default::default()).unwrap();
 
 // Creating a `TextDisplay` which contains the elements required to draw a specific sentence.
 let text = glium_text::TextDisplay::new(&system, &font, "Hello world!");
@@ -29,28 +29,335 @@ glium_text::draw(&text, &system, &mut display.draw(), matrix, (1.0, 1.0, 0.0, 1.
 # }
 ```
 
+## Backends
+
+By default the glyphs are rasterized with the FreeType library. Enabling the `pure-rust`
+feature (and disabling the default features) uses a parser and a rasterizer written in pure
+Rust instead, which makes cross-compiling easier.
+
+## Color glyphs
+
+Color glyphs, like the emoji of `sbix`, `CBDT` or `COLR` fonts, are drawn with their own
+colors. Only the alpha of the color passed to `draw` is applied to them.
+
 */
 
 #![warn(missing_docs)]
 
+#[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
 extern crate libc;
+#[cfg(all(feature = "freetype", not(feature = "pure-rust")))]
 extern crate freetype_sys as freetype;
+#[cfg(feature = "pure-rust")]
+extern crate ttf_parser;
+#[cfg(feature = "pure-rust")]
+extern crate ab_glyph_rasterizer;
+#[cfg(feature = "pure-rust")]
+extern crate png;
 #[macro_use]
 extern crate glium;
+extern crate unicode_bidi;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
+use backend::GlyphSource;
+use glium::CapabilitiesSource;
 use glium::DrawParameters;
 use glium::backend::Context;
 use glium::backend::Facade;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::default::Default;
 use std::io::Read;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
+use std::path::Path;
 use std::rc::Rc;
 
+mod atlas;
+mod backend;
+mod layout;
+#[cfg(feature = "system-fonts")]
+pub mod system_fonts;
+
+pub use layout::{Feature, LayoutOptions, Rect, TabStops, TextMetrics, Truncation, WhiteSpace,
+                 WritingMode};
+
 /// Texture which contains the characters of the font.
 pub struct FontTexture {
-    texture: glium::texture::Texture2d,
-    character_infos: Vec<(char, CharacterInfos)>,
+    // the glyphs are spread over several textures if they don't fit in one
+    pages: Vec<TexturePage>,
+    character_infos: HashMap<GlyphKey, CharacterInfos>,
+    variations: Vec<Variation>,
+    subpixel: Option<SubpixelOrder>,
+    // number of horizontal positions of each glyph in the texture
+    subpixel_positions: u32,
+    // number of pixels per EM in the texture
+    em_pixels: f32,
+    // every size the glyphs are rasterized at, in increasing order, including `em_pixels`
+    pixel_sizes: Vec<u32>,
+    metrics: FontMetrics,
+    mark_classes: MarkClasses,
+    base_anchors: BaseAnchors,
+    // number of bytes per pixel of the coverage in the textures
+    coverage_bytes: usize,
+}
+
+// the infos of the glyphs are looked up by character, pixel size, phase and feature of the
+// alternate glyphs
+type GlyphKey = (char, u32, u32, Option<[u8; 4]>);
+
+// for each combining mark, its classes with its anchor, in EMs
+type MarkClasses = HashMap<char, Vec<(u32, (f32, f32))>>;
+
+// anchors of the bases for each class of marks, in EMs
+type BaseAnchors = HashMap<(char, u32), (f32, f32)>;
+
+/// Options for building a `FontTexture`.
+#[derive(Clone, Debug, Default)]
+pub struct FontOptions {
+    /// Index of the face to load in a font collection, like a `.ttc` or `.otc` file.
+    ///
+    /// Regular font files only contain the face `0`, which is the default.
+    pub face_index: u32,
+
+    /// For variable fonts, index of the named instance to use, in the list returned by
+    /// `FontTexture::named_instances`.
+    ///
+    /// The default instance of the font is used if `None`.
+    pub named_instance: Option<usize>,
+
+    /// For variable fonts, values of the variation axes. They take precedence over the
+    /// coordinates of the named instance.
+    ///
+    /// Values outside of the range of an axis are clamped, and axes that the font doesn't
+    /// have are ignored.
+    pub variations: Vec<Variation>,
+
+    /// Rasterizes the glyphs for subpixel antialiasing on a screen with this order of subpixels,
+    /// instead of grayscale antialiasing.
+    ///
+    /// Only `draw_subpixel` makes use of it ; `draw` averages the subpixels.
+    pub subpixel: Option<SubpixelOrder>,
+
+    /// How the glyphs are rasterized.
+    pub raster: RasterOptions,
+
+    /// How the glyphs are placed in the texture.
+    pub atlas: AtlasOptions,
+
+    /// Number of horizontal positions at which each glyph is rasterized, for example `3` for
+    /// glyphs offset by a third of a pixel.
+    ///
+    /// When drawing, each glyph uses the position that is nearest to its exact position, which
+    /// gives a more regular spacing to small texts. This takes as many times more room in the
+    /// texture. `0` and `1`, the default, disable subpixel positioning.
+    ///
+    /// The positions are computed for the size of the texture, so this only helps if the text
+    /// is drawn with one pixel of the texture for each pixel of the screen.
+    pub subpixel_positions: u32,
+
+    /// Other sizes at which the glyphs are added to the texture, in pixels per EM, for the texts
+    /// with a `LayoutOptions::pixel_size`. The default is empty.
+    ///
+    /// Each size takes as much room in the texture as a texture of this size alone.
+    ///
+    /// The glyphs are only rasterized at these sizes and at the `font_size`, when the texture is
+    /// built, and never on demand: a text displayed at another size uses the smallest of them
+    /// that is larger, or the largest one, scaled to its size.
+    pub sizes: Vec<u32>,
+
+    /// OpenType features whose glyphs are added to the texture, so that
+    /// `LayoutOptions::features` can enable them, for example `*b"tnum"` or `*b"smcp"`.
+    ///
+    /// Only the features that replace a glyph with another one are supported: the ligature
+    /// (type 4) and contextual lookups are ignored, so `*b"liga"` adds nothing. The vertical
+    /// alternates of `vert` are always added.
+    pub features: Vec<[u8; 4]>,
+}
+
+/// Options for rasterizing the glyphs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RasterOptions {
+    /// How the outlines are fitted to the pixel grid. The default is `Hinting::Normal`.
+    pub hinting: Hinting,
+
+    /// If false, the pixels are either fully covered or empty. This also disables subpixel
+    /// antialiasing. The default is `true`.
+    pub antialiasing: bool,
+
+    /// Gamma applied to the coverage of the pixels. Values above `1.0` make the text darker
+    /// and thicker, values below make it lighter. The default is `1.0`.
+    pub gamma: f32,
+
+    /// Between `-1.0` and `1.0`. Increases the contrast between the covered and the empty
+    /// pixels when positive, and reduces it when negative. `0.0`, the default, doesn't change
+    /// anything.
+    pub contrast: f32,
+
+    /// Makes the glyphs bolder, for fonts that don't have a bold face. The default is `false`.
+    pub embolden: bool,
+
+    /// Slants the glyphs, for fonts that don't have an italic face. The default is `false`.
+    pub oblique: bool,
+}
+
+impl Default for RasterOptions {
+    fn default() -> RasterOptions {
+        RasterOptions {
+            hinting: Hinting::Normal,
+            antialiasing: true,
+            gamma: 1.0,
+            contrast: 0.0,
+            embolden: false,
+            oblique: false,
+        }
+    }
+}
+
+/// Options for placing the glyphs in the texture.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasOptions {
+    /// Number of empty pixels around each glyph, so that the glyphs don't bleed into each
+    /// other when the texture is filtered. The default is `2`.
+    pub padding: u32,
+
+    /// Rounds the width and the height of the texture up to powers of two, for old hardware.
+    /// The default is `true`.
+    pub power_of_two: bool,
+
+    /// Maximum width and height of the texture, in pixels. The maximum size supported by the
+    /// OpenGL context is used if it is smaller, or if this is `None`, the default.
+    pub max_size: Option<u32>,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> AtlasOptions {
+        AtlasOptions {
+            padding: 2,
+            power_of_two: true,
+            max_size: None,
+        }
+    }
+}
+
+/// How the outlines of the glyphs are fitted to the pixel grid.
+///
+/// The `pure-rust` backend doesn't support hinting, and always behaves like `Hinting::None`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Hinting {
+    /// The outlines are rasterized as they are. The shapes are the most faithful, but the text
+    /// is blurrier.
+    None,
+    /// The outlines are only fitted vertically, which keeps the shapes of the glyphs.
+    Light,
+    /// The hinting instructions of the font are used, or the auto-hinter if it has none.
+    Normal,
+    /// The auto-hinter of FreeType is used, even if the font has instructions.
+    Auto,
+}
+
+/// Metrics of a font, in EMs.
+///
+/// The values are relative to the base line and grow upwards, like the coordinates used by
+/// `draw`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// Height above the base line of the highest glyphs.
+    pub ascender: f32,
+
+    /// Position of the bottom of the lowest glyphs. Usually negative.
+    pub descender: f32,
+
+    /// Space between the descender of a line and the ascender of the next one.
+    pub line_gap: f32,
+
+    /// Height of the capital letters, like `H`.
+    ///
+    /// Measured on the glyph if the font doesn't specify it, or set to the ascender if the font
+    /// has no `H`.
+    pub cap_height: f32,
+
+    /// Height of the lowercase letters, like `x`.
+    ///
+    /// Measured on the glyph if the font doesn't specify it, or estimated from the height of the
+    /// capital letters if the font has no `x`.
+    pub x_height: f32,
+
+    /// Position of the center of the underline.
+    pub underline_position: f32,
+
+    /// Thickness of the underline.
+    pub underline_thickness: f32,
+}
+
+impl FontMetrics {
+    /// Returns the distance between the base lines of two consecutive lines of text.
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+}
+
+/// Order of the red, green and blue subpixels of the pixels of a screen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubpixelOrder {
+    /// Red, green and blue from left to right. This is the most common order.
+    Rgb,
+    /// Blue, green and red from left to right.
+    Bgr,
+    /// Red, green and blue from top to bottom.
+    VerticalRgb,
+    /// Blue, green and red from top to bottom.
+    VerticalBgr,
+}
+
+impl SubpixelOrder {
+    fn is_vertical(&self) -> bool {
+        *self == SubpixelOrder::VerticalRgb || *self == SubpixelOrder::VerticalBgr
+    }
+
+    fn is_bgr(&self) -> bool {
+        *self == SubpixelOrder::Bgr || *self == SubpixelOrder::VerticalBgr
+    }
+}
+
+/// Value of a variation axis of a variable font.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Variation {
+    /// Tag of the axis, for example `*b"wght"` for the weight or `*b"wdth"` for the width.
+    pub tag: [u8; 4],
+
+    /// Value in the units of the axis, for example `700.0` on the `wght` axis for a bold text.
+    pub value: f32,
+}
+
+/// Description of a variation axis of a variable font.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariationAxis {
+    /// Tag of the axis, for example `*b"wght"`.
+    pub tag: [u8; 4],
+
+    /// Name of the axis as written in the font, for example `Weight`.
+    pub name: String,
+
+    /// Minimum value of the axis.
+    pub min: f32,
+
+    /// Value of the axis in the default instance.
+    pub default: f32,
+
+    /// Maximum value of the axis.
+    pub max: f32,
+}
+
+/// A predefined set of coordinates of a variable font, like `Bold Condensed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedInstance {
+    /// Name of the instance as written in the font.
+    pub name: String,
+
+    /// Value of each axis, in the same order as the list returned by
+    /// `FontTexture::variation_axes`.
+    pub coordinates: Vec<f32>,
 }
 
 /// Object that contains the elements shared by all `TextDisplay` objects.
@@ -59,6 +366,11 @@ pub struct FontTexture {
 pub struct TextSystem {
     context: Rc<Context>,
     program: glium::Program,
+    subpixel_program: glium::Program,
+    // program for subpixel antialiasing with dual-source blending, `None` if the context
+    // doesn't support it
+    dual_source_program: Option<glium::Program>,
+    selection_program: glium::Program,
 }
 
 /// Object that will allow you to draw a text.
@@ -66,9 +378,35 @@ pub struct TextDisplay<F> where F: Deref<Target=FontTexture> {
     context: Rc<Context>,
     texture: F,
     vertex_buffer: Option<glium::VertexBuffer<VertexFormat>>,
-    index_buffer: Option<glium::IndexBuffer<u16>>,
+    // the quads drawn with each page of the texture, in the order they are drawn ; the indices
+    // are 32 bits because a screen of cells with backgrounds has more than 65536 vertices
+    index_buffers: Vec<(usize, glium::IndexBuffer<u32>)>,
     total_text_width: f32,
     is_empty: bool,
+    has_colored_glyphs: bool,
+    has_cell_colors: bool,
+    options: LayoutOptions,
+    layout: layout::Layout,
+}
+
+/// Colors of a grapheme cluster, for `TextDisplay::set_text_with_colors`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CellColors {
+    /// Color of the glyphs. Color glyphs, like emoji, only use its alpha.
+    pub foreground: (f32, f32, f32, f32),
+
+    /// Color of the rectangle behind the cluster, which has the height of the line. The
+    /// default is `None`, which doesn't draw it.
+    pub background: Option<(f32, f32, f32, f32)>,
+}
+
+impl Default for CellColors {
+    fn default() -> CellColors {
+        CellColors {
+            foreground: (1.0, 1.0, 1.0, 1.0),
+            background: None,
+        }
+    }
 }
 
 // structure containing informations about a character of a font
@@ -91,23 +429,76 @@ struct CharacterInfos {
 
     // number of EMs at the right of the character
     right_padding: f32,
+
+    // true if the character must be drawn with the colors of `color_texture`
+    colored: bool,
+
+    // index of the horizontal position of the glyph, when there are several
+    phase: u32,
+
+    // index of the texture of the glyph in `FontTexture::pages`
+    page: usize,
+
+    // number of EMs between the tops of two consecutive characters of a vertical text, and
+    // between the top of the character and the base line of its horizontal metrics
+    vertical_advance: f32,
+    vertical_origin: f32,
+
+    // the feature that replaces the character with this glyph, like `vert` for the vertical
+    // alternates, or `None` for the default glyph
+    feature: Option<[u8; 4]>,
+
+    // number of pixels per EM the glyph was rasterized at
+    pixel_size: u32,
+}
+
+/// A texture of the glyphs of a `FontTexture`.
+struct TexturePage {
+    texture: glium::texture::Texture2d,
+    // colors of the color glyphs, like emoji, at the same positions as in `texture`
+    color_texture: glium::texture::Texture2d,
 }
 
 struct TextureData {
-    data: Vec<f32>,
+    data: Vec<u8>,
     width: u32,
     height: u32,
+    // `U8` for grayscale antialiasing, `U8U8U8` for subpixel antialiasing, or `U8U8U8U8` once
+    // expanded
+    format: glium::texture::ClientFormat,
+    // RGBA pixels of the color glyphs, `None` if the font doesn't have any
+    colors: Option<Vec<u8>>,
+}
+
+impl TextureData {
+    /// Copies the coverage to the RGB channels of RGBA pixels, for the contexts that don't
+    /// support `U8` or `U8U8U8` textures.
+    fn expand_to_rgba(&mut self) {
+        let channels = match self.format {
+            glium::texture::ClientFormat::U8U8U8 => 3,
+            _ => 1,
+        };
+
+        let mut data = Vec::with_capacity(self.data.len() / channels * 4);
+        for pixel in self.data.chunks(channels) {
+            data.extend((0 .. 3).map(|channel| pixel[channel % channels]));
+            data.push(255);
+        }
+
+        self.data = data;
+        self.format = glium::texture::ClientFormat::U8U8U8U8;
+    }
 }
 
 impl<'a> glium::texture::Texture2dDataSource<'a> for &'a TextureData {
-    type Data = f32;
+    type Data = u8;
 
-    fn into_raw(self) -> glium::texture::RawImage2d<'a, f32> {
+    fn into_raw(self) -> glium::texture::RawImage2d<'a, u8> {
         glium::texture::RawImage2d {
             data: Cow::Borrowed(&self.data),
             width: self.width,
             height: self.height,
-            format: glium::texture::ClientFormat::F32,
+            format: self.format,
         }
     }
 }
@@ -116,100 +507,367 @@ impl<'a> glium::texture::Texture2dDataSource<'a> for &'a TextureData {
 struct VertexFormat {
     position: [f32; 2],
     tex_coords: [f32; 2],
+    colored: f32,
+    // multiplied by the color passed to `draw`
+    vertex_color: [f32; 4],
+    // 1.0 for the backgrounds of the clusters, which don't use the textures
+    background: f32,
 }
 
-implement_vertex!(VertexFormat, position, tex_coords);
+implement_vertex!(VertexFormat, position, tex_coords, colored, vertex_color, background);
 
 impl FontTexture {
     /// Creates a new texture representing a font stored in a `FontTexture`.
-    pub fn new<R, F>(facade: &F, font: R, font_size: u32)
+    pub fn new<R, F>(facade: &F, mut font: R, font_size: u32)
                      -> Result<FontTexture, ()> where R: Read, F: Facade
     {
-        // building the freetype library
-        // FIXME: call FT_Done_Library
-        let library = unsafe {
-            // taken from https://github.com/PistonDevelopers/freetype-rs/blob/master/src/library.rs
-            extern "C" fn alloc_library(_memory: freetype::FT_Memory, size: libc::c_long) -> *mut libc::c_void {
-                unsafe {
-                    libc::malloc(size as libc::size_t)
-                }
-            }
-            extern "C" fn free_library(_memory: freetype::FT_Memory, block: *mut libc::c_void) {
-                unsafe {
-                    libc::free(block)
-                }
-            }
-            extern "C" fn realloc_library(_memory: freetype::FT_Memory,
-                                          _cur_size: libc::c_long,
-                                          new_size: libc::c_long,
-                                          block: *mut libc::c_void) -> *mut libc::c_void {
-                unsafe {
-                    libc::realloc(block, new_size as libc::size_t)
-                }
+        let mut data = Vec::new();
+        if font.read_to_end(&mut data).is_err() {
+            return Err(());
+        }
+
+        FontTexture::from_bytes(facade, &data, font_size, &Default::default())
+    }
+
+    /// Creates a new texture from the content of a font file.
+    ///
+    /// The data is only borrowed while the texture is being built, and is not copied.
+    ///
+    /// If the glyphs don't fit in a texture of `AtlasOptions::max_size`, they are spread over
+    /// several textures, and each `TextDisplay` is drawn with one draw call per texture.
+    pub fn from_bytes<F>(facade: &F, font: &[u8], font_size: u32, options: &FontOptions)
+                         -> Result<FontTexture, ()> where F: Facade
+    {
+        // building the face object
+        let mut face = backend::Face::new(font, options.face_index)?;
+        let variations = apply_variations(&mut face, options)?;
+
+        // computing the list of characters in the font
+        let characters_list = face.characters();
+        let (mark_classes, base_anchors) = mark_attachments(&face, &characters_list);
+        let alternates = feature_alternates(&face, &characters_list, &options.features);
+
+        // building the infos
+        // monochrome glyphs have no subpixels
+        let subpixel = if options.raster.antialiasing { options.subpixel } else { None };
+
+        // the other sizes come first, so that the face is at `font_size` for the metrics
+        let mut pixel_sizes: Vec<u32> = options.sizes.iter().cloned()
+                                               .filter(|&size| size > 0 && size != font_size)
+                                               .collect();
+        pixel_sizes.sort();
+        pixel_sizes.dedup();
+        pixel_sizes.push(font_size);
+
+        // the texture can't be larger than what the OpenGL implementation supports
+        let max_texture_size = facade.get_context().get_capabilities().max_texture_size as u32;
+        let atlas = AtlasOptions {
+            max_size: Some(options.atlas.max_size.map_or(max_texture_size,
+                                                          |size| size.min(max_texture_size))),
+            .. options.atlas
+        };
+
+        let parameters = RasterParameters {
+            subpixel: subpixel,
+            raster: options.raster,
+            subpixel_positions: options.subpixel_positions.max(1),
+            atlas: atlas,
+        };
+        let (pages_data, chr_infos) = build_font_image(&mut face, characters_list,
+                                                       alternates, &pixel_sizes,
+                                                       &parameters)?;
+        let metrics = face.metrics();
+        pixel_sizes.sort();
+
+        // the first glyph wins if several have the same key
+        let mut character_infos = HashMap::with_capacity(chr_infos.len());
+        for (character, infos) in chr_infos {
+            character_infos.entry((character, infos.pixel_size, infos.phase, infos.feature))
+                           .or_insert(infos);
+        }
+
+        // we load the textures in the display ; the coverage is uploaded as bytes, and the
+        // textures don't have mipmaps because the glyphs are only sampled linearly
+        use glium::texture::{MipmapsOption, UncompressedFloatFormat};
+        let format = if subpixel.is_some() {
+            UncompressedFloatFormat::U8U8U8
+        } else {
+            UncompressedFloatFormat::U8
+        };
+
+        // `U8` needs OpenGL 3.0 or `GL_ARB_texture_rg` ; otherwise the coverage goes in RGBA
+        // textures, where the shaders read it the same way
+        let (format, coverage_bytes) = if format.is_supported(&**facade.get_context()) {
+            (format, if subpixel.is_some() { 3 } else { 1 })
+        } else {
+            (UncompressedFloatFormat::U8U8U8U8, 4)
+        };
+
+        let mut pages = Vec::with_capacity(pages_data.len());
+        for mut texture_data in pages_data {
+            if coverage_bytes == 4 {
+                texture_data.expand_to_rgba();
             }
-            static mut MEMORY: freetype::FT_MemoryRec = freetype::FT_MemoryRec {
-                user: 0 as *mut libc::c_void,
-                alloc: alloc_library,
-                free: free_library,
-                realloc: realloc_library,
+
+            let texture = glium::texture::Texture2d::with_format(facade, &texture_data, format,
+                                                                 MipmapsOption::NoMipmap)
+                                                                 .map_err(|_| ())?;
+            let color_texture = match texture_data.colors {
+                Some(colors) => {
+                    let image = glium::texture::RawImage2d::from_raw_rgba(colors,
+                                                                          (texture_data.width,
+                                                                           texture_data.height));
+                    glium::texture::Texture2d::with_format(facade, image,
+                                                           UncompressedFloatFormat::U8U8U8U8,
+                                                           MipmapsOption::NoMipmap)
+                                                           .map_err(|_| ())?
+                },
+                None => glium::texture::Texture2d::empty(facade, 1, 1).map_err(|_| ())?,
             };
 
-            let mut raw = ::std::ptr::null_mut();
-            if freetype::FT_New_Library(&mut MEMORY, &mut raw) != freetype::FT_Err_Ok {
-                return Err(());
-            }
-            freetype::FT_Add_Default_Modules(raw);
+            pages.push(TexturePage {
+                texture: texture,
+                color_texture: color_texture,
+            });
+        }
+
+        Ok(FontTexture {
+            pages: pages,
+            character_infos: character_infos,
+            variations: variations,
+            subpixel: subpixel,
+            subpixel_positions: parameters.subpixel_positions,
+            em_pixels: font_size as f32,
+            pixel_sizes: pixel_sizes,
+            metrics: metrics,
+            mark_classes: mark_classes,
+            base_anchors: base_anchors,
+            coverage_bytes: coverage_bytes,
+        })
+    }
+
+    /// Creates a new texture from a font file on the disk.
+    pub fn from_file<F, P>(facade: &F, path: P, font_size: u32, options: &FontOptions)
+                           -> Result<FontTexture, ()> where F: Facade, P: AsRef<Path>
+    {
+        let data = std::fs::read(path).map_err(|_| ())?;
+        FontTexture::from_bytes(facade, &data, font_size, options)
+    }
+
+    /// Returns the variation axes of a face, or an empty list if it isn't a variable font.
+    pub fn variation_axes(font: &[u8], face_index: u32) -> Result<Vec<VariationAxis>, ()> {
+        Ok(backend::Face::new(font, face_index)?.variation_axes())
+    }
+
+    /// Returns the named instances of a face, or an empty list if it isn't a variable font.
+    pub fn named_instances(font: &[u8], face_index: u32) -> Result<Vec<NamedInstance>, ()> {
+        Ok(backend::Face::new(font, face_index)?.named_instances())
+    }
+
+    /// Returns the value of each variation axis that was used to build this texture.
+    ///
+    /// The list is empty if the font isn't a variable font.
+    pub fn variation_coordinates(&self) -> &[Variation] {
+        &self.variations
+    }
 
-            raw
+    /// Returns the metrics of the font, in EMs.
+    pub fn metrics(&self) -> FontMetrics {
+        self.metrics
+    }
+
+    /// Returns the width of the cells of `LayoutOptions::grid`, in EMs.
+    ///
+    /// This is the advance of `0`, or of a space if the font doesn't have it, rounded to whole
+    /// pixels of the texture so that the cells stay aligned on the pixels.
+    pub fn cell_width(&self) -> f32 {
+        layout::cell_width(self, &Default::default())
+    }
+
+    /// Returns the sizes at which the glyphs are in the texture, in pixels per EM and in
+    /// increasing order. This is the `font_size` of the texture and `FontOptions::sizes`.
+    pub fn pixel_sizes(&self) -> &[u32] {
+        &self.pixel_sizes
+    }
+
+    /// Returns the number of bytes of video memory used by the textures of the glyphs.
+    ///
+    /// The coverage of the glyphs takes one byte per pixel, or three with subpixel
+    /// antialiasing, and the colors of the color glyphs take four bytes per pixel. The
+    /// coverage takes four bytes per pixel too if the OpenGL context doesn't support textures
+    /// with fewer channels.
+    pub fn memory_usage(&self) -> usize {
+        let channels = self.coverage_bytes;
+        let bytes = |texture: &glium::texture::Texture2d, channels: usize| {
+            texture.get_width() as usize * texture.get_height().unwrap_or(1) as usize * channels
         };
 
-        // building the freetype face object
-        let font: Vec<u8> = font.bytes().map(|c| c.unwrap()).collect();
+        self.pages.iter()
+            .map(|page| bytes(&page.texture, channels) + bytes(&page.color_texture, 4))
+            .sum()
+    }
 
-        let face: freetype::FT_Face = unsafe {
-            let mut face = ::std::ptr::null_mut();
-            let err = freetype::FT_New_Memory_Face(library, font.as_ptr(),
-                                                   font.len() as freetype::FT_Long, 0, &mut face);
-            if err == freetype::FT_Err_Ok {
-                face
-            } else {
-                return Err(());
-            }
+    /// Returns the dimensions of a text, as it would be drawn by a `TextDisplay` with the same
+    /// options.
+    ///
+    /// This only uses the metrics of the glyphs and doesn't need the OpenGL context.
+    pub fn measure(&self, text: &str, options: &LayoutOptions) -> TextMetrics {
+        layout::layout(self, text, options).metrics
+    }
+
+    /// Returns the order of the subpixels the texture was built for, or `None` if it uses
+    /// grayscale antialiasing.
+    pub fn subpixel_order(&self) -> Option<SubpixelOrder> {
+        self.subpixel
+    }
+}
+
+impl layout::GlyphMetrics for FontTexture {
+    fn metrics(&self) -> FontMetrics {
+        self.metrics
+    }
+
+    fn em_pixels(&self) -> f32 {
+        self.em_pixels
+    }
+
+    fn subpixel_positions(&self) -> u32 {
+        self.subpixel_positions
+    }
+
+    fn nearest_size(&self, pixel_size: f32) -> u32 {
+        nearest_size(&self.pixel_sizes, pixel_size)
+    }
+
+    fn character_infos(&self, character: char, size: u32, phase: u32) -> Option<CharacterInfos> {
+        self.character_infos.get(&(character, size, phase, None)).cloned()
+    }
+
+    fn alternate_infos(&self, character: char, size: u32, phase: u32, feature: &[u8; 4])
+                       -> Option<CharacterInfos>
+    {
+        self.character_infos.get(&(character, size, phase, Some(*feature))).cloned()
+    }
+
+    fn mark_offset(&self, base: char, mark: char) -> Option<(f32, f32)> {
+        let classes = match self.mark_classes.get(&mark) {
+            Some(classes) => classes,
+            None => return None
         };
 
-        // computing the list of characters in the font
-        let characters_list = unsafe {
-            // TODO: unresolved symbol
-            /*if freetype::FT_Select_CharMap(face, freetype::FT_ENCODING_UNICODE) != 0 {
-                return Err(());
-            }*/
+        for &(class, mark_anchor) in classes {
+            if let Some(base_anchor) = self.base_anchors.get(&(base, class)) {
+                return Some((base_anchor.0 - mark_anchor.0, base_anchor.1 - mark_anchor.1));
+            }
+        }
+
+        None
+    }
+}
+
+// chooses among sizes in increasing order the smallest one that is at least `pixel_size`, or
+// the largest one
+fn nearest_size(sizes: &[u32], pixel_size: f32) -> u32 {
+    sizes.iter().cloned()
+         .find(|&size| size as f32 >= pixel_size - 1e-3)
+         .unwrap_or(*sizes.last().unwrap())
+}
+
+// converts the mark anchors of the glyphs of a face to anchors of characters
+fn mark_attachments<S>(face: &S, characters_list: &[(char, u32)])
+                       -> (MarkClasses, BaseAnchors)
+                       where S: GlyphSource
+{
+    let mut glyph_characters: HashMap<u32, Vec<char>> = HashMap::new();
+    for &(chr, glyph) in characters_list {
+        glyph_characters.entry(glyph).or_insert_with(Vec::new).push(chr);
+    }
+
+    let glyphs: Vec<u32> = glyph_characters.keys().cloned().collect();
+    let anchors = face.mark_anchors(&glyphs);
+
+    let mut mark_classes = HashMap::new();
+    for (glyph, class, anchor) in anchors.marks {
+        // the classes stay in the order of the lookups, so that the first one that attaches a
+        // mark to a base wins
+        for &chr in &glyph_characters[&glyph] {
+            mark_classes.entry(chr).or_insert_with(Vec::new).push((class, anchor));
+        }
+    }
+
+    let mut base_anchors = HashMap::new();
+    for (glyph, class, anchor) in anchors.bases {
+        for &chr in &glyph_characters[&glyph] {
+            base_anchors.insert((chr, class), anchor);
+        }
+    }
+
+    (mark_classes, base_anchors)
+}
 
-            let mut result = Vec::new();
+// returns the characters of a face that are replaced by the vertical alternates and by the
+// given features, with the glyph that replaces them and the feature
+fn feature_alternates<S>(face: &S, characters_list: &[(char, u32)], features: &[[u8; 4]])
+                         -> Vec<(char, u32, [u8; 4])>
+                         where S: GlyphSource
+{
+    let glyphs: Vec<u32> = characters_list.iter().map(|&(_, glyph)| glyph).collect();
 
-            let mut g: freetype::FT_UInt = std::mem::uninitialized();
-            let mut c = freetype::FT_Get_First_Char(face, &mut g);
+    let mut tags = vec![*b"vert"];
+    for tag in features {
+        if !tags.contains(tag) {
+            tags.push(*tag);
+        }
+    }
 
-            while g != 0 {
-                result.push(std::mem::transmute(c as u32));     // TODO: better solution?
-                c = freetype::FT_Get_Next_Char(face, c, &mut g);
+    let mut result = Vec::new();
+    for tag in tags {
+        let alternates: HashMap<u32, u32> = face.substitutions(&tag, &glyphs).into_iter()
+                                                .collect();
+        for &(chr, glyph) in characters_list {
+            if let Some(&alternate) = alternates.get(&glyph) {
+                result.push((chr, alternate, tag));
             }
+        }
+    }
 
-            result
-        };
+    result
+}
 
-        // building the infos
-        let (texture_data, chr_infos) = unsafe {
-            build_font_image(face, characters_list, font_size)
+// sets the coordinates of a variable font, and returns the value of each of its axes
+fn apply_variations<S>(face: &mut S, options: &FontOptions) -> Result<Vec<Variation>, ()>
+                       where S: GlyphSource
+{
+    let axes = face.variation_axes();
+    if axes.is_empty() {
+        return match options.named_instance {
+            Some(_) => Err(()),
+            None => Ok(Vec::new()),
         };
+    }
 
-        // we load the texture in the display
-        let texture = glium::texture::Texture2d::new(facade, &texture_data).unwrap();
+    let mut coordinates: Vec<f32> = axes.iter().map(|a| a.default).collect();
 
-        Ok(FontTexture {
-            texture: texture,
-            character_infos: chr_infos,
-        })
+    if let Some(index) = options.named_instance {
+        match face.named_instances().get(index) {
+            Some(instance) if instance.coordinates.len() == axes.len() => {
+                coordinates.copy_from_slice(&instance.coordinates);
+            },
+            _ => return Err(())
+        }
+    }
+
+    for variation in &options.variations {
+        if let Some(index) = axes.iter().position(|a| a.tag == variation.tag) {
+            coordinates[index] = variation.value.max(axes[index].min).min(axes[index].max);
+        }
     }
+
+    face.set_variations(&coordinates)?;
+
+    Ok(axes.iter().zip(coordinates.into_iter())
+               .map(|(axis, value)| Variation { tag: axis.tag, value: value })
+               .collect())
 }
 
 /*impl glium::uniforms::AsUniformValue for FontTexture {
@@ -221,6 +879,10 @@ impl FontTexture {
 impl TextSystem {
     /// Builds a new text system that must be used to build `TextDisplay` objects.
     pub fn new<F>(facade: &F) -> TextSystem where F: Facade {
+        // dual-source blending is part of OpenGL 3.3
+        let version = facade.get_context().get_version().clone();
+        let dual_source = version >= glium::Version(glium::Api::Gl, 3, 3);
+
         TextSystem {
             context: facade.get_context().clone(),
             program: program!(facade, 
@@ -231,22 +893,45 @@ impl TextSystem {
                         uniform mat4 matrix;
                         in vec2 position;
                         in vec2 tex_coords;
+                        in float colored;
+                        in vec4 vertex_color;
+                        in float background;
 
                         out vec2 v_tex_coords;
+                        out float v_colored;
+                        out vec4 v_color;
+                        out float v_background;
 
                         void main() {
                             gl_Position = matrix * vec4(position, 0.0, 1.0);
                             v_tex_coords = tex_coords;
+                            v_colored = colored;
+                            v_color = vertex_color;
+                            v_background = background;
                         }
                     ",
                     fragment: "
                         #version 140
                         in vec2 v_tex_coords;
+                        in float v_colored;
+                        in vec4 v_color;
+                        in float v_background;
                         out vec4 f_color;
                         uniform vec4 color;
                         uniform sampler2D tex;
+                        uniform sampler2D color_tex;
+                        uniform vec3 coverage_weights;
                         void main() {
-                            vec4 c = vec4(color.rgb, color.a * texture(tex, v_tex_coords));
+                            vec4 c;
+                            if (v_background > 0.5) {
+                                c = vec4(v_color.rgb, v_color.a * color.a);
+                            } else if (v_colored > 0.5) {
+                                vec4 texel = texture(color_tex, v_tex_coords);
+                                c = vec4(texel.rgb, texel.a * v_color.a * color.a);
+                            } else {
+                                float coverage = dot(texture(tex, v_tex_coords).rgb, coverage_weights);
+                                c = vec4(v_color.rgb * color.rgb, v_color.a * color.a * coverage);
+                            }
                             if (c.a <= 0.01) {
                                 discard;
                             } else {
@@ -262,23 +947,45 @@ impl TextSystem {
 
                         attribute vec2 position;
                         attribute vec2 tex_coords;
+                        attribute float colored;
+                        attribute vec4 vertex_color;
+                        attribute float background;
                         varying vec2 v_tex_coords;
+                        varying float v_colored;
+                        varying vec4 v_color;
+                        varying float v_background;
                         uniform mat4 matrix;
 
                         void main() {
                             gl_Position = matrix * vec4(position.x, position.y, 0.0, 1.0);
                             v_tex_coords = tex_coords;
+                            v_colored = colored;
+                            v_color = vertex_color;
+                            v_background = background;
                         }
                     ",
                     fragment: "
                         #version 110
 
                         varying vec2 v_tex_coords;
+                        varying float v_colored;
+                        varying vec4 v_color;
+                        varying float v_background;
                         uniform vec4 color;
                         uniform sampler2D tex;
+                        uniform sampler2D color_tex;
+                        uniform vec3 coverage_weights;
 
                         void main() {
-                            gl_FragColor = vec4(color.rgb, color.a * texture2D(tex, v_tex_coords));
+                            if (v_background > 0.5) {
+                                gl_FragColor = vec4(v_color.rgb, v_color.a * color.a);
+                            } else if (v_colored > 0.5) {
+                                vec4 texel = texture2D(color_tex, v_tex_coords);
+                                gl_FragColor = vec4(texel.rgb, texel.a * v_color.a * color.a);
+                            } else {
+                                float coverage = dot(texture2D(tex, v_tex_coords).rgb, coverage_weights);
+                                gl_FragColor = vec4(v_color.rgb * color.rgb, v_color.a * color.a * coverage);
+                            }
                             if (gl_FragColor.a <= 0.01) {
                                 discard;
                             }
@@ -286,110 +993,385 @@ impl TextSystem {
                     "
                 },
 
-            ).unwrap()
-        }
-    }
-}
-
-impl<F> TextDisplay<F> where F: Deref<Target=FontTexture> {
-    /// Builds a new text display that allows you to draw text.
-    pub fn new(system: &TextSystem, texture: F, text: &str) -> TextDisplay<F> {
-        let mut text_display = TextDisplay {
-            context: system.context.clone(),
-            texture: texture,
-            vertex_buffer: None,
-            index_buffer: None,
-            total_text_width: 0.0,
-            is_empty: true,
-        };
-
-        text_display.set_text(text);
+            ).unwrap(),
 
-        text_display
-    }
+            // used without dual-source blending ; the color of the text is passed as the constant
+            // blending color, and the fragment shader outputs the coverage of each subpixel ;
+            // colored glyphs are drawn separately
+            subpixel_program: program!(facade,
+                140 => {
+                    vertex: "
+                        #version 140
 
-    /// Returns the width in GL units of the text.
-    pub fn get_width(&self) -> f32 {
-        self.total_text_width
-    }
+                        uniform mat4 matrix;
+                        in vec2 position;
+                        in vec2 tex_coords;
+                        in float colored;
 
-    /// Modifies the text on this display.
-    pub fn set_text(&mut self, text: &str) {
-        self.is_empty = true;
-        self.total_text_width = 0.0;
-        self.vertex_buffer = None;
-        self.index_buffer = None;
+                        out vec2 v_tex_coords;
+                        out float v_colored;
 
-        // returning if no text
-        if text.len() == 0 {
-            return;
-        }
+                        void main() {
+                            gl_Position = matrix * vec4(position, 0.0, 1.0);
+                            v_tex_coords = tex_coords;
+                            v_colored = colored;
+                        }
+                    ",
+                    fragment: "
+                        #version 140
+                        in vec2 v_tex_coords;
+                        in float v_colored;
+                        out vec4 f_color;
+                        uniform vec4 color;
+                        uniform sampler2D tex;
+                        void main() {
+                            vec3 coverage = texture(tex, v_tex_coords).rgb * color.a;
+                            if (v_colored > 0.5 || max(coverage.r, max(coverage.g, coverage.b)) <= 0.01) {
+                                discard;
+                            } else {
+                                f_color = vec4(coverage, 1.0);
+                            }
+                        }
+                    "
+                },
 
-        // these arrays will contain the vertex buffer and index buffer data
-        let mut vertex_buffer_data = Vec::with_capacity(text.len() * 4 * 4);
-        let mut index_buffer_data = Vec::with_capacity(text.len() * 6);
-
-        // iterating over the characters of the string
-        for character in text.chars() {     // FIXME: wrong, but only thing stable
-            let infos = match self.texture.character_infos
-                .iter().find(|&&(chr, _)| chr == character)
-            {
-                Some(infos) => infos,
-                None => continue        // character not found in the font, ignoring it
-            };
-            let infos = infos.1;
+                110 => {
+                    vertex: "
+                        #version 110
 
-            self.is_empty = false;
+                        attribute vec2 position;
+                        attribute vec2 tex_coords;
+                        attribute float colored;
+                        varying vec2 v_tex_coords;
+                        varying float v_colored;
+                        uniform mat4 matrix;
 
-            // adding the quad in the index buffer
-            {
-                let first_vertex_offset = vertex_buffer_data.len() as u16;
-                index_buffer_data.push(first_vertex_offset);
-                index_buffer_data.push(first_vertex_offset + 1);
-                index_buffer_data.push(first_vertex_offset + 2);
-                index_buffer_data.push(first_vertex_offset + 2);
-                index_buffer_data.push(first_vertex_offset + 1);
-                index_buffer_data.push(first_vertex_offset + 3);
-            }
+                        void main() {
+                            gl_Position = matrix * vec4(position.x, position.y, 0.0, 1.0);
+                            v_tex_coords = tex_coords;
+                            v_colored = colored;
+                        }
+                    ",
+                    fragment: "
+                        #version 110
 
-            //
-            self.total_text_width += infos.left_padding;
+                        varying vec2 v_tex_coords;
+                        varying float v_colored;
+                        uniform vec4 color;
+                        uniform sampler2D tex;
 
-            // calculating coords
-            let left_coord = self.total_text_width;
-            let right_coord = left_coord + infos.size.0;
-            let top_coord = infos.height_over_line;
-            let bottom_coord = infos.height_over_line - infos.size.1;
+                        void main() {
+                            vec3 coverage = texture2D(tex, v_tex_coords).rgb * color.a;
+                            if (v_colored > 0.5 || max(coverage.r, max(coverage.g, coverage.b)) <= 0.01) {
+                                discard;
+                            }
+                            gl_FragColor = vec4(coverage, 1.0);
+                        }
+                    "
+                },
 
-            // top-left vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [left_coord, top_coord],
-                tex_coords: [infos.tex_coords.0, infos.tex_coords.1],
-            });
+            ).unwrap(),
+
+            // the fragment shader outputs the color of the text and the coverage of each
+            // subpixel, which are blended with `SRC1_COLOR` ; colored glyphs and backgrounds use
+            // the same coverage for the three subpixels
+            dual_source_program: if dual_source {
+                Some(program!(facade,
+                    330 => {
+                        vertex: "
+                            #version 330
+
+                            uniform mat4 matrix;
+                            in vec2 position;
+                            in vec2 tex_coords;
+                            in float colored;
+                            in vec4 vertex_color;
+                            in float background;
+
+                            out vec2 v_tex_coords;
+                            out float v_colored;
+                            out vec4 v_color;
+                            out float v_background;
+
+                            void main() {
+                                gl_Position = matrix * vec4(position, 0.0, 1.0);
+                                v_tex_coords = tex_coords;
+                                v_colored = colored;
+                                v_color = vertex_color;
+                                v_background = background;
+                            }
+                        ",
+                        fragment: "
+                            #version 330
+                            in vec2 v_tex_coords;
+                            in float v_colored;
+                            in vec4 v_color;
+                            in float v_background;
+                            layout(location = 0, index = 0) out vec4 f_color;
+                            layout(location = 0, index = 1) out vec4 f_coverage;
+                            uniform vec4 color;
+                            uniform sampler2D tex;
+                            uniform sampler2D color_tex;
+                            void main() {
+                                vec3 coverage;
+                                if (v_background > 0.5) {
+                                    f_color = vec4(v_color.rgb, 1.0);
+                                    coverage = vec3(v_color.a * color.a);
+                                } else if (v_colored > 0.5) {
+                                    vec4 texel = texture(color_tex, v_tex_coords);
+                                    f_color = vec4(texel.rgb, 1.0);
+                                    coverage = vec3(texel.a * v_color.a * color.a);
+                                } else {
+                                    f_color = vec4(v_color.rgb * color.rgb, 1.0);
+                                    coverage = texture(tex, v_tex_coords).rgb * v_color.a * color.a;
+                                }
+                                float max_coverage = max(coverage.r, max(coverage.g, coverage.b));
+                                if (max_coverage <= 0.01) {
+                                    discard;
+                                }
+                                f_coverage = vec4(coverage, max_coverage);
+                            }
+                        "
+                    },
+                ).unwrap())
+            } else {
+                None
+            },
 
-            // top-right vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [right_coord, top_coord],
-                tex_coords: [infos.tex_coords.0 + infos.tex_size.0, infos.tex_coords.1],
-            });
+            // plain quads for the background of the selected text
+            selection_program: program!(facade,
+                140 => {
+                    vertex: "
+                        #version 140
 
-            // bottom-left vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [left_coord, bottom_coord],
-                tex_coords: [infos.tex_coords.0, infos.tex_coords.1 + infos.tex_size.1],
-            });
+                        uniform mat4 matrix;
+                        in vec2 position;
 
-            // bottom-right vertex
-            vertex_buffer_data.push(VertexFormat {
-                position: [right_coord, bottom_coord],
-                tex_coords: [
-                    infos.tex_coords.0 + infos.tex_size.0,
-                    infos.tex_coords.1 + infos.tex_size.1
-                ],
-            });
+                        void main() {
+                            gl_Position = matrix * vec4(position, 0.0, 1.0);
+                        }
+                    ",
+                    fragment: "
+                        #version 140
+                        out vec4 f_color;
+                        uniform vec4 color;
+                        void main() {
+                            f_color = color;
+                        }
+                    "
+                },
+
+                110 => {
+                    vertex: "
+                        #version 110
+
+                        attribute vec2 position;
+                        uniform mat4 matrix;
+
+                        void main() {
+                            gl_Position = matrix * vec4(position.x, position.y, 0.0, 1.0);
+                        }
+                    ",
+                    fragment: "
+                        #version 110
+
+                        uniform vec4 color;
+
+                        void main() {
+                            gl_FragColor = color;
+                        }
+                    "
+                },
+
+            ).unwrap(),
+        }
+    }
+}
+
+impl<F> TextDisplay<F> where F: Deref<Target=FontTexture> {
+    /// Builds a new text display that allows you to draw text.
+    pub fn new(system: &TextSystem, texture: F, text: &str) -> TextDisplay<F> {
+        TextDisplay::with_options(system, texture, text, Default::default())
+    }
+
+    /// Builds a new text display with options for the layout of the text.
+    pub fn with_options(system: &TextSystem, texture: F, text: &str, options: LayoutOptions)
+                        -> TextDisplay<F>
+    {
+        let layout = layout::layout(&*texture, "", &options);
+        let mut text_display = TextDisplay {
+            context: system.context.clone(),
+            texture: texture,
+            vertex_buffer: None,
+            index_buffers: Vec::new(),
+            total_text_width: 0.0,
+            is_empty: true,
+            has_colored_glyphs: false,
+            has_cell_colors: false,
+            options: options,
+            layout: layout,
+        };
+
+        text_display.set_text(text);
 
-            // going to next char
-            self.total_text_width = right_coord + infos.right_padding;
+        text_display
+    }
+
+    /// Returns the width in GL units of the text. For texts of several lines, this is the width
+    /// of the longest line.
+    pub fn get_width(&self) -> f32 {
+        self.total_text_width
+    }
+
+    /// Returns the number of pixels per EM of the text when it is drawn with `draw_pixels`,
+    /// which is `LayoutOptions::pixel_size` or the `font_size` of the texture.
+    pub fn pixel_size(&self) -> f32 {
+        self.options.pixel_size.unwrap_or(self.texture.em_pixels)
+    }
+
+    /// Builds the matrix used by `draw_pixels` for this text, with one EM for each
+    /// `pixel_size` pixels. See the `pixel_matrix` function.
+    pub fn pixel_matrix(&self, target_dimensions: (u32, u32), position: (f32, f32))
+                        -> [[f32; 4]; 4]
+    {
+        em_pixel_matrix(self.pixel_size(), target_dimensions, position)
+    }
+
+    /// Returns the byte offsets of the boundaries between the grapheme clusters of the text. See
+    /// `TextMetrics::cluster_boundaries`.
+    pub fn cluster_boundaries(&self) -> &[usize] {
+        &self.layout.metrics.cluster_boundaries
+    }
+
+    /// Returns the byte ranges of the text that are displayed. See
+    /// `TextMetrics::visible_ranges`.
+    pub fn visible_ranges(&self) -> &[Range<usize>] {
+        &self.layout.metrics.visible_ranges
+    }
+
+    /// Returns the byte offset in the text of the caret position closest to `point`.
+    ///
+    /// The point is in the same coordinates as the text when it is drawn: in EMs, relative to
+    /// the start of the base line of the first line, or to the top-right corner of the first
+    /// column with `WritingMode::VerticalRl`. The offset is always at the boundary of a
+    /// grapheme cluster, and clicking on the right half of a right-to-left character places the
+    /// caret before it.
+    pub fn hit_test(&self, point: (f32, f32)) -> usize {
+        self.layout.hit_test(point)
+    }
+
+    /// Returns the position of the caret placed before the byte at `offset` in the text, as
+    /// `(x, y, height)` where `(x, y)` is the bottom of the caret.
+    ///
+    /// The coordinates are the same as for `hit_test`. An offset inside a grapheme cluster is
+    /// moved to the start of the cluster, and an offset at the end of a line puts the caret
+    /// after its last character, which is on the left for a right-to-left line.
+    ///
+    /// With `WritingMode::VerticalRl` the caret is horizontal, and this returns
+    /// `(x, y, width)` where `(x, y)` is its left end.
+    pub fn caret_position(&self, offset: usize) -> (f32, f32, f32) {
+        self.layout.caret_position(offset)
+    }
+
+    /// Returns the rectangles covering the characters of a range of bytes of the text, with the
+    /// full height of their line.
+    ///
+    /// The coordinates are the same as for `hit_test`. A selection that spans several lines or
+    /// several runs of a bidirectional text gives several rectangles.
+    pub fn selection_rects(&self, range: Range<usize>) -> Vec<Rect> {
+        self.layout.selection_rects(range)
+    }
+
+    /// Modifies the text on this display.
+    pub fn set_text(&mut self, text: &str) {
+        self.set_text_with_colors(text, &[]);
+    }
+
+    /// Modifies the text on this display, with the colors of each of its grapheme clusters.
+    ///
+    /// `colors` has one entry per grapheme cluster of the text, in order and without the line
+    /// breaks, like the cells of a terminal. The clusters without an entry use the default
+    /// `CellColors`. The foreground colors are multiplied by the color passed to `draw`, and only
+    /// the alpha of the latter is applied to the backgrounds.
+    ///
+    /// Without dual-source blending, `draw_subpixel` can't blend several colors in one pass, so
+    /// it falls back to `draw` for the texts with colors.
+    pub fn set_text_with_colors(&mut self, text: &str, colors: &[CellColors]) {
+        self.is_empty = true;
+        self.has_colored_glyphs = false;
+        self.has_cell_colors = !colors.is_empty();
+        self.total_text_width = 0.0;
+        self.vertex_buffer = None;
+        self.index_buffers.clear();
+        self.layout = layout::layout(&*self.texture, text, &self.options);
+
+        // returning if no text
+        if text.len() == 0 {
+            return;
+        }
+
+        // these arrays will contain the vertex buffer and index buffer data ; there is an index
+        // buffer for each page of the texture, and the backgrounds are in the first one so that
+        // they are drawn before all the glyphs
+        let mut vertex_buffer_data = Vec::with_capacity(text.len() * 4 * 4);
+        let mut index_buffer_data: Vec<Vec<u32>> = self.texture.pages.iter()
+                                                       .map(|_| Vec::new()).collect();
+
+        self.total_text_width = self.layout.metrics.width;
+
+        // the colors of each cluster of the layout
+        let cluster_colors: Vec<CellColors> = self.layout.cells(text).into_iter().map(|cell| {
+            cell.and_then(|index| colors.get(index).cloned()).unwrap_or_default()
+        }).collect();
+
+        // the backgrounds are drawn first, with the height of their line so that they don't
+        // leave gaps between the lines
+        for line in &self.layout.lines {
+            for index in line.clusters.clone() {
+                let cluster = &self.layout.clusters[index];
+                let background = match cluster_colors[index].background {
+                    Some(background) => background,
+                    None => continue
+                };
+
+                let rect = self.layout.line_rect(line, cluster.x, cluster.x + cluster.advance,
+                                                 true);
+                self.is_empty = false;
+                push_quad(&mut vertex_buffer_data, &mut index_buffer_data[0],
+                          [rect.left, rect.bottom + rect.height, rect.left + rect.width,
+                           rect.bottom],
+                          [[0.0; 2]; 4], false, background, true);
+            }
+        }
+
+        // iterating over the glyphs of the text
+        for glyph in &self.layout.glyphs {
+            let infos = glyph.infos;
+
+            self.is_empty = false;
+            self.has_colored_glyphs |= infos.colored;
+
+            // the texture coordinates of the top-left, top-right, bottom-left and bottom-right
+            // corners of the glyph
+            let (u0, v0) = infos.tex_coords;
+            let (u1, v1) = (u0 + infos.tex_size.0, v0 + infos.tex_size.1);
+
+            // calculating coords ; sideways glyphs are rotated clockwise around their origin,
+            // so their left edge is at the top
+            let (position, tex_coords) = if glyph.sideways {
+                let right_coord = glyph.origin.0 + infos.height_over_line;
+                let top_coord = glyph.origin.1 - infos.left_padding;
+                ([right_coord - infos.size.1, top_coord, right_coord, top_coord - infos.size.0],
+                 [[u0, v1], [u0, v0], [u1, v1], [u1, v0]])
+            } else {
+                let left_coord = glyph.origin.0 + infos.left_padding;
+                let top_coord = glyph.origin.1 + infos.height_over_line;
+                ([left_coord, top_coord, left_coord + infos.size.0, top_coord - infos.size.1],
+                 [[u0, v0], [u1, v0], [u0, v1], [u1, v1]])
+            };
+
+            push_quad(&mut vertex_buffer_data, &mut index_buffer_data[infos.page], position,
+                      tex_coords, infos.colored, cluster_colors[glyph.cluster].foreground, false);
         }
 
         if !vertex_buffer_data.len() != 0 {
@@ -397,50 +1379,320 @@ impl<F> TextDisplay<F> where F: Deref<Target=FontTexture> {
             self.vertex_buffer = Some(glium::VertexBuffer::new(&self.context,
                                                                &vertex_buffer_data).unwrap());
 
-            // building the index buffer
-            self.index_buffer = Some(glium::IndexBuffer::new(&self.context,
-                                     glium::index::PrimitiveType::TrianglesList,
-                                     &index_buffer_data).unwrap());
+            // building the index buffers of the pages that are used
+            for (page, indices) in index_buffer_data.into_iter().enumerate() {
+                if indices.is_empty() {
+                    continue;
+                }
+
+                let index_buffer = glium::IndexBuffer::new(&self.context,
+                                         glium::index::PrimitiveType::TrianglesList,
+                                         &indices).unwrap();
+                self.index_buffers.push((page, index_buffer));
+            }
         }
     }
 }
 
+// adds a quad to the vertex and index buffer data ; the position is left, top, right and
+// bottom, and the texture coordinates are those of the top-left, top-right, bottom-left and
+// bottom-right corners
+fn push_quad(vertex_buffer_data: &mut Vec<VertexFormat>, index_buffer_data: &mut Vec<u32>,
+             position: [f32; 4], tex_coords: [[f32; 2]; 4], colored: bool,
+             color: (f32, f32, f32, f32), background: bool)
+{
+    let first_vertex_offset = vertex_buffer_data.len() as u32;
+    for &index in &[0, 1, 2, 2, 1, 3] {
+        index_buffer_data.push(first_vertex_offset + index);
+    }
+
+    // top-left, top-right, bottom-left and bottom-right vertices
+    for (corner, &(x, y)) in [(0, 1), (2, 1), (0, 3), (2, 3)].iter().enumerate() {
+        vertex_buffer_data.push(VertexFormat {
+            position: [position[x], position[y]],
+            tex_coords: tex_coords[corner],
+            colored: if colored { 1.0 } else { 0.0 },
+            vertex_color: [color.0, color.1, color.2, color.3],
+            background: if background { 1.0 } else { 0.0 },
+        });
+    }
+}
+
 ///
 /// ## About the matrix
 ///
 /// The matrix must be column-major post-muliplying (which is the usual way to do in OpenGL).
 ///
-/// One unit in height corresponds to a line of text, but the text can go above or under.
-/// The bottom of the line is at `0.0`, the top is at `1.0`.
+/// One unit corresponds to one EM, which is `font_size` pixels in the `FontTexture`. The base
+/// line of the text is at `0.0`, and the `FontMetrics` of the texture give the position of the
+/// ascender and the descender. The following lines of a text are below the first one, see
+/// `FontTexture::measure` for the dimensions of the whole text.
 /// You need to adapt your matrix by taking these into consideration.
+///
+/// `draw_pixels` builds the matrix for you to draw the text at a position in pixels.
 pub fn draw<F, S: ?Sized, M>(text: &TextDisplay<F>, system: &TextSystem, target: &mut S,
                              matrix: M, color: (f32, f32, f32, f32))
                              where S: glium::Surface, M: Into<[[f32; 4]; 4]>,
                                    F: Deref<Target=FontTexture>
 {
+    // textures built for subpixel antialiasing are drawn with the average of the subpixels
+    let coverage_weights = match text.texture.subpixel {
+        Some(_) => [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0],
+        None => [1.0, 0.0, 0.0],
+    };
+
+    draw_with_weights(text, system, target, matrix.into(), color, coverage_weights);
+}
+
+/// Draws a text with one pixel of the `FontTexture` on each pixel of the target.
+///
+/// The text is drawn at its `TextDisplay::pixel_size`. If this isn't one of the sizes of the
+/// texture, the glyphs are scaled.
+///
+/// `position` is the left of the base line of the text, or the top-right corner of the first
+/// column of a vertical text, in pixels from the top-left corner of the target. It is rounded
+/// to a whole pixel, so that the glyphs aren't blurry.
+pub fn draw_pixels<F, S: ?Sized>(text: &TextDisplay<F>, system: &TextSystem, target: &mut S,
+                                 position: (f32, f32), color: (f32, f32, f32, f32))
+                                 where S: glium::Surface, F: Deref<Target=FontTexture>
+{
+    let matrix = text.pixel_matrix(target.get_dimensions(), position);
+    draw(text, system, target, matrix, color);
+}
+
+/// Builds the matrix used by `draw_pixels`, for a target with the given dimensions.
+///
+/// This can be passed to `draw` or `draw_subpixel`. For a text with a
+/// `LayoutOptions::pixel_size`, use `TextDisplay::pixel_matrix` instead.
+pub fn pixel_matrix(font: &FontTexture, target_dimensions: (u32, u32), position: (f32, f32))
+                    -> [[f32; 4]; 4]
+{
+    em_pixel_matrix(font.em_pixels, target_dimensions, position)
+}
+
+// builds the matrix of `pixel_matrix` for a number of pixels per EM
+fn em_pixel_matrix(em_pixels: f32, target_dimensions: (u32, u32), position: (f32, f32))
+                   -> [[f32; 4]; 4]
+{
+    let (width, height) = (target_dimensions.0 as f32, target_dimensions.1 as f32);
+    let (x, y) = (position.0.round(), position.1.round());
+
+    // one EM is `em_pixels` pixels, and the Y axis of the target goes up
+    [
+        [2.0 * em_pixels / width, 0.0, 0.0, 0.0],
+        [0.0, 2.0 * em_pixels / height, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [2.0 * x / width - 1.0, 1.0 - 2.0 * y / height, 0.0, 1.0],
+    ]
+}
+
+/// Draws a text with subpixel antialiasing.
+///
+/// The `FontTexture` must have been built with `FontOptions::subpixel`, otherwise this is the
+/// same as `draw`. See `draw` for the matrix.
+///
+/// With OpenGL 3.3 and above, the text is drawn in one pass with dual-source blending, and the
+/// alpha channel of the target is written like with `draw`. Older versions blend the text with
+/// the constant color of the blending equation instead: the text must then be drawn on an
+/// opaque background, because the alpha channel of the target isn't modified, and the texts
+/// with colors are drawn with `draw`.
+pub fn draw_subpixel<F, S: ?Sized, M>(text: &TextDisplay<F>, system: &TextSystem,
+                                      target: &mut S, matrix: M, color: (f32, f32, f32, f32))
+                                      where S: glium::Surface, M: Into<[[f32; 4]; 4]>,
+                                            F: Deref<Target=FontTexture>
+{
+    if text.texture.subpixel.is_none() {
+        return draw(text, system, target, matrix, color);
+    }
+
     let matrix = matrix.into();
 
-    let &TextDisplay { ref vertex_buffer, ref index_buffer, ref texture, is_empty, .. } = text;
-    let color = [color.0, color.1, color.2, color.3];
+    if let Some(ref program) = system.dual_source_program {
+        return draw_dual_source(text, program, target, matrix, color);
+    }
+
+    if text.has_cell_colors {
+        return draw(text, system, target, matrix, color);
+    }
+
+    let &TextDisplay { ref vertex_buffer, ref index_buffers, ref texture, is_empty,
+                       has_colored_glyphs, .. } = text;
 
     // returning if nothing to draw
-    if is_empty || vertex_buffer.is_none() || index_buffer.is_none() {
+    if is_empty || vertex_buffer.is_none() {
         return;
     }
 
     let vertex_buffer = vertex_buffer.as_ref().unwrap();
-    let index_buffer = index_buffer.as_ref().unwrap();
 
-    let uniforms = uniform! {
-        matrix: matrix,
-        color: color,
-        tex: glium::uniforms::Sampler(&texture.texture, glium::uniforms::SamplerBehavior {
-            magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
-            minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+    // destination = color * coverage + destination * (1 - coverage), for each subpixel
+    let params = {
+        use glium::BlendingFunction::Addition;
+        use glium::LinearBlendingFactor::*;
+
+        let blend = glium::Blend {
+            color: Addition {
+                source: ConstantColor,
+                destination: OneMinusSourceColor
+            },
+            alpha: Addition {
+                source: Zero,
+                destination: One
+            },
+            constant_value: color,
+        };
+
+        DrawParameters {
+            blend: blend,
             .. Default::default()
-        })
+        }
+    };
+
+    // one draw for each page of the texture
+    for &(page, ref index_buffer) in index_buffers {
+        let uniforms = uniform! {
+            matrix: matrix,
+            color: [color.0, color.1, color.2, color.3],
+            tex: glium::uniforms::Sampler(&texture.pages[page].texture,
+                                          glium::uniforms::SamplerBehavior {
+                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+                .. Default::default()
+            }),
+        };
+
+        target.draw(vertex_buffer, index_buffer, &system.subpixel_program, &uniforms,
+                    &params).unwrap();
+    }
+
+    // the colored glyphs were discarded by the subpixel program, and the regular program
+    // discards all the others when the weights are zero
+    if has_colored_glyphs {
+        draw_with_weights(text, system, target, matrix, color, [0.0, 0.0, 0.0]);
+    }
+}
+
+// draws with subpixel antialiasing and dual-source blending
+fn draw_dual_source<F, S: ?Sized>(text: &TextDisplay<F>, program: &glium::Program, target: &mut S,
+                                  matrix: [[f32; 4]; 4], color: (f32, f32, f32, f32))
+                                  where S: glium::Surface, F: Deref<Target=FontTexture>
+{
+    let &TextDisplay { ref vertex_buffer, ref index_buffers, ref texture, is_empty, .. } = text;
+
+    // returning if nothing to draw
+    if is_empty || vertex_buffer.is_none() {
+        return;
+    }
+
+    let vertex_buffer = vertex_buffer.as_ref().unwrap();
+
+    // destination = color * coverage + destination * (1 - coverage), for each subpixel ; the
+    // alpha uses the largest coverage of the three subpixels
+    let params = {
+        use glium::BlendingFunction::Addition;
+        use glium::LinearBlendingFactor::*;
+
+        let blend = glium::Blend {
+            color: Addition {
+                source: SourceOneColor,
+                destination: OneMinusSourceOneColor
+            },
+            alpha: Addition {
+                source: SourceOneAlpha,
+                destination: OneMinusSourceOneAlpha
+            },
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        };
+
+        DrawParameters {
+            blend: blend,
+            .. Default::default()
+        }
+    };
+
+    // one draw for each page of the texture
+    for &(page, ref index_buffer) in index_buffers {
+        let page = &texture.pages[page];
+        let uniforms = uniform! {
+            matrix: matrix,
+            color: [color.0, color.1, color.2, color.3],
+            tex: glium::uniforms::Sampler(&page.texture, glium::uniforms::SamplerBehavior {
+                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+                .. Default::default()
+            }),
+            color_tex: glium::uniforms::Sampler(&page.color_texture,
+                                                glium::uniforms::SamplerBehavior {
+                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+                .. Default::default()
+            })
+        };
+
+        target.draw(vertex_buffer, index_buffer, program, &uniforms, &params).unwrap();
+    }
+}
+
+/// Draws the background of the selected part of a text, as returned by
+/// `TextDisplay::selection_rects`.
+///
+/// The matrix is the same as for `draw`. Call this before drawing the text, so that the text is
+/// drawn over the background.
+pub fn draw_selection<F, S: ?Sized, M>(text: &TextDisplay<F>, system: &TextSystem,
+                                       target: &mut S, matrix: M, range: Range<usize>,
+                                       color: (f32, f32, f32, f32))
+                                       where S: glium::Surface, M: Into<[[f32; 4]; 4]>,
+                                             F: Deref<Target=FontTexture>
+{
+    let rects = text.selection_rects(range);
+    if rects.is_empty() {
+        return;
+    }
+
+    let mut vertex_buffer_data = Vec::with_capacity(rects.len() * 4);
+    let mut index_buffer_data = Vec::with_capacity(rects.len() * 6);
+
+    for rect in rects {
+        push_quad(&mut vertex_buffer_data, &mut index_buffer_data,
+                  [rect.left, rect.bottom + rect.height, rect.left + rect.width, rect.bottom],
+                  [[0.0; 2]; 4], false, color, true);
+    }
+
+    let vertex_buffer = glium::VertexBuffer::new(&text.context, &vertex_buffer_data).unwrap();
+    let index_buffer = glium::IndexBuffer::new(&text.context,
+                                               glium::index::PrimitiveType::TrianglesList,
+                                               &index_buffer_data).unwrap();
+
+    let uniforms = uniform! {
+        matrix: matrix.into(),
+        color: [color.0, color.1, color.2, color.3],
+    };
+
+    let params = DrawParameters {
+        blend: glium::Blend::alpha_blending(),
+        .. Default::default()
     };
 
+    target.draw(&vertex_buffer, &index_buffer, &system.selection_program, &uniforms,
+                &params).unwrap();
+}
+
+// draws with the regular program ; `coverage_weights` are the weights of the channels of the
+// texture used to compute the coverage
+fn draw_with_weights<F, S: ?Sized>(text: &TextDisplay<F>, system: &TextSystem, target: &mut S,
+                                   matrix: [[f32; 4]; 4], color: (f32, f32, f32, f32),
+                                   coverage_weights: [f32; 3])
+                                   where S: glium::Surface, F: Deref<Target=FontTexture>
+{
+    let &TextDisplay { ref vertex_buffer, ref index_buffers, ref texture, is_empty, .. } = text;
+    let color = [color.0, color.1, color.2, color.3];
+
+    // returning if nothing to draw
+    if is_empty || vertex_buffer.is_none() {
+        return;
+    }
+
+    let vertex_buffer = vertex_buffer.as_ref().unwrap();
 
     let params = {
         use glium::BlendingFunction::Addition;
@@ -462,130 +1714,207 @@ pub fn draw<F, S: ?Sized, M>(text: &TextDisplay<F>, system: &TextSystem, target:
             .. Default::default()
         }
     };
-    target.draw(vertex_buffer, index_buffer, &system.program, &uniforms,
-                &params).unwrap();
-}
-
-unsafe fn build_font_image(face: freetype::FT_Face, characters_list: Vec<char>, font_size: u32)
-                           -> (TextureData, Vec<(char, CharacterInfos)>)
-{
-    use std::iter;
 
-    // a margin around each character to prevent artifacts
-    const MARGIN: u32 = 2;
+    // one draw for each page of the texture
+    for &(page, ref index_buffer) in index_buffers {
+        let page = &texture.pages[page];
+        let uniforms = uniform! {
+            matrix: matrix,
+            color: color,
+            coverage_weights: coverage_weights,
+            tex: glium::uniforms::Sampler(&page.texture, glium::uniforms::SamplerBehavior {
+                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+                .. Default::default()
+            }),
+            color_tex: glium::uniforms::Sampler(&page.color_texture,
+                                                glium::uniforms::SamplerBehavior {
+                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+                .. Default::default()
+            })
+        };
 
-    // setting the right pixel size
-    if freetype::FT_Set_Pixel_Sizes(face, font_size, font_size) != 0 {
-        panic!();
+        target.draw(vertex_buffer, index_buffer, &system.program, &uniforms,
+                    &params).unwrap();
     }
+}
 
-    // this variable will store the texture data
-    // we set an arbitrary capacity that we think will match what we will need
-    let mut texture_data: Vec<f32> = Vec::with_capacity(characters_list.len() *
-                                                        font_size as usize * font_size as usize);
+/// How `build_font_image` rasterizes the glyphs and places them in the textures.
+struct RasterParameters {
+    // `None` for grayscale antialiasing
+    subpixel: Option<SubpixelOrder>,
+    raster: RasterOptions,
+    // number of horizontal positions of each glyph, at least 1
+    subpixel_positions: u32,
+    atlas: AtlasOptions,
+}
 
-    // the width is chosen more or less arbitrarily, because we can store everything as long as
-    //  the texture is at least as wide as the widest character
-    // we just try to estimate a width so that width ~= height
-    let texture_width = get_nearest_po2(std::cmp::max(font_size * 2 as u32,
-        ((((characters_list.len() as u32) * font_size * font_size) as f32).sqrt()) as u32));
+// the textures, and the infos of each glyph they contain
+type FontImage = (Vec<TextureData>, Vec<(char, CharacterInfos)>);
 
-    // we store the position of the "cursor" in the destination texture
-    // this cursor points to the top-left pixel of the next character to write on the texture
-    let mut cursor_offset = (0u32, 0u32);
+fn build_font_image<S>(face: &mut S, characters_list: Vec<(char, u32)>,
+                       alternates: Vec<(char, u32, [u8; 4])>, font_sizes: &[u32],
+                       parameters: &RasterParameters) -> Result<FontImage, ()>
+                       where S: GlyphSource
+{
+    let RasterParameters { subpixel, ref raster, subpixel_positions, ref atlas } = *parameters;
 
-    // number of rows to skip at next carriage return
-    let mut rows_to_skip = 0u32;
+    // checking that the face can be rasterized at each size ; the glyphs are rasterized one
+    // size after the other
+    for &font_size in font_sizes {
+        face.set_pixel_size(font_size)?;
+    }
+    let mut current_size = match font_sizes.last() {
+        Some(&font_size) => font_size,
+        None => return Err(())
+    };
+    face.set_raster_options(raster, subpixel);
+
+    // with subpixel antialiasing each pixel of the texture has a coverage for each subpixel,
+    // in the red, green and blue channels
+    let channels = if subpixel.is_some() { 3 } else { 1 };
+
+    // rasterizing all the glyphs first, so that they can be packed from the tallest to the
+    // shortest
+    let metrics = face.metrics();
+    let (characters_list, alternates) = (&characters_list, &alternates);
+    let glyphs_list = font_sizes.iter().flat_map(move |&font_size| {
+        characters_list.iter().map(|&(character, glyph)| (character, glyph, None))
+            .chain(alternates.iter().map(|&(character, glyph, tag)| (character, glyph, Some(tag))))
+            .map(move |(character, glyph, feature)| (font_size, character, glyph, feature))
+    }).flat_map(|(font_size, character, glyph, feature)| {
+        (0 .. subpixel_positions).map(move |phase| (font_size, character, glyph, feature, phase))
+    });
+    let glyphs: Vec<(char, CharacterInfos, backend::GlyphBitmap)> = glyphs_list.filter_map(|(font_size, character, glyph, feature, phase)| {
+        if font_size != current_size {
+            face.set_pixel_size(font_size).ok()?;
+            current_size = font_size;
+        }
 
-    // now looping through the list of characters, filling the texture and returning the informations
-    let mut em_pixels = font_size as f32;
-    let mut characters_infos: Vec<(char, CharacterInfos)> = characters_list.into_iter().filter_map(|character| {
         // loading wanted glyph in the font face
-        if freetype::FT_Load_Glyph(face, freetype::FT_Get_Char_Index(face, character as freetype::FT_ULong), freetype::FT_LOAD_RENDER) != 0 {
-            return None;
-        }
-        let bitmap = &(*(*face).glyph).bitmap;
+        let x_offset = phase as f32 / subpixel_positions as f32;
+        let bitmap = match face.rasterize(glyph, x_offset) {
+            Some(bitmap) => bitmap,
+            None => return None
+        };
 
-        // adding a left margin before our character to prevent artifacts
-        cursor_offset.0 += MARGIN;
+        // fonts without vertical metrics are laid out in squares of the height of a line
+        let (vertical_advance, vertical_origin) = face.vertical_metrics(glyph).unwrap_or(
+            (metrics.ascender - metrics.descender, metrics.ascender));
 
-        // computing em_pixels
-        // FIXME: this is hacky
-        if character == 'M' {
-            em_pixels = bitmap.rows as f32;
-        }
+        // filling infos about that character
+        // tex_size and tex_coords are in pixels for the moment ; they will be divided
+        // by the texture dimensions later, once the glyph is placed
+        let infos = CharacterInfos {
+            tex_size: (bitmap.width as f32, bitmap.rows as f32),
+            tex_coords: (0.0, 0.0),
+            size: (bitmap.width as f32, bitmap.rows as f32),
+            left_padding: bitmap.left as f32,
+            right_padding: bitmap.advance - bitmap.width as f32 - bitmap.left as f32,
+            height_over_line: bitmap.top as f32,
+            colored: bitmap.format == backend::BitmapFormat::Rgba,
+            phase: phase,
+            page: 0,
+            vertical_advance: vertical_advance,
+            vertical_origin: vertical_origin,
+            feature: feature,
+            pixel_size: font_size,
+        };
+        Some((character, infos, bitmap))
+    }).collect();
 
-        // carriage return our cursor if we don't have enough room to write the next caracter
-        // we add a margin to prevent artifacts
-        if cursor_offset.0 + (bitmap.width as u32) + MARGIN >= texture_width {
-            assert!(bitmap.width as u32 <= texture_width);       // if this fails, we should increase texture_width
-            cursor_offset.0 = 0;
-            cursor_offset.1 += rows_to_skip;
-            rows_to_skip = 0;
-        }
+    // placing the glyphs in the texture
+    let mut order: Vec<usize> = (0 .. glyphs.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&glyphs[a].2, &glyphs[b].2);
+        b.rows.cmp(&a.rows).then(b.width.cmp(&a.width))
+    });
+    let sizes: Vec<(u32, u32)> = order.iter()
+                                      .map(|&index| (glyphs[index].2.width, glyphs[index].2.rows))
+                                      .collect();
+    let max_size = atlas.max_size.unwrap_or(std::u32::MAX);
+    let (pages, positions) = match atlas::pack(&sizes, atlas, max_size) {
+        Some(packing) => packing,
+        None => return Err(())
+    };
 
-        // if the texture data buffer has not enough lines, adding some
-        if rows_to_skip < MARGIN + bitmap.rows as u32 {
-            let diff = MARGIN + (bitmap.rows as u32) - rows_to_skip;
-            rows_to_skip = MARGIN + bitmap.rows as u32;
-            texture_data.extend(iter::repeat(0.0).take((diff * texture_width) as usize));
+    let mut glyph_positions = vec![(0, 0, 0); glyphs.len()];
+    for (&index, &position) in order.iter().zip(positions.iter()) {
+        glyph_positions[index] = position;
+    }
+
+    // copying the glyphs to the textures ; the color glyphs go in other textures of the same
+    // dimensions, and their coverage is left empty
+    let mut pages_data: Vec<TextureData> = pages.iter().enumerate().map(|(page, &(width, height))| {
+        let colored = glyphs.iter().zip(glyph_positions.iter())
+                            .any(|(glyph, position)| glyph.1.colored && position.0 == page);
+        TextureData {
+            data: vec![0; (width * height * channels) as usize],
+            width: width,
+            height: height,
+            format: if subpixel.is_some() {
+                glium::texture::ClientFormat::U8U8U8
+            } else {
+                glium::texture::ClientFormat::U8
+            },
+            colors: if colored { Some(vec![0u8; (width * height * 4) as usize]) } else { None },
         }
+    }).collect();
 
-        // copying the data to the texture
-        let offset_x_before_copy = cursor_offset.0;
-        if bitmap.rows >= 1 {
-            let destination = &mut texture_data[(cursor_offset.0 + cursor_offset.1 * texture_width) as usize ..];
-            let source = std::mem::transmute(bitmap.buffer);
-            let source = std::slice::from_raw_parts(source, destination.len());
+    let mut characters_infos = Vec::with_capacity(glyphs.len());
+    for ((character, mut infos, bitmap), (page, x, y)) in glyphs.into_iter().zip(glyph_positions) {
+        let texture = &mut pages_data[page];
+        let texture_width = texture.width;
+
+        if infos.colored {
+            let colors = texture.colors.as_mut().unwrap();
+            for row in 0 .. bitmap.rows {
+                let source = &bitmap.buffer[(row * bitmap.width * 4) as usize .. ((row + 1) * bitmap.width * 4) as usize];
+                let offset = ((y + row) * texture_width + x) as usize * 4;
+                colors[offset .. offset + source.len()].copy_from_slice(source);
+            }
+        } else if bitmap.rows >= 1 {
+            let destination = &mut texture.data[((x + y * texture_width) * channels) as usize ..];
+            let lcd = bitmap.format == backend::BitmapFormat::Lcd;
+            let source_channels = if lcd { 3 } else { 1 };
 
-            for y in 0 .. bitmap.rows as u32 {
-                let source = &source[(y * bitmap.width as u32) as usize ..];
-                let destination = &mut destination[(y * texture_width) as usize ..];
+            for y in 0 .. bitmap.rows {
+                let source = &bitmap.buffer[(y * bitmap.width * source_channels) as usize ..];
+                let destination = &mut destination[(y * texture_width * channels) as usize ..];
 
                 for x in 0 .. bitmap.width {
-                    // the values in source are bytes between 0 and 255, but we want floats between 0 and 1
-                    let val: u8 = *source.get(x as usize).unwrap();
-                    let val = (val as f32) / (std::u8::MAX as f32);
-                    let dest = destination.get_mut(x as usize).unwrap();
-                    *dest = val;
+                    for channel in 0 .. channels {
+                        // the subpixels are in the order of the screen, and the texture is in RGB
+                        let source_channel = match subpixel {
+                            _ if !lcd => 0,
+                            Some(order) if order.is_bgr() => 2 - channel,
+                            _ => channel,
+                        };
+
+                        let val: u8 = *source.get((x * source_channels + source_channel) as usize).unwrap();
+                        let val = adjust_coverage(val, raster);
+                        let dest = destination.get_mut((x * channels + channel) as usize).unwrap();
+                        *dest = val;
+                    }
                 }
             }
-
-            cursor_offset.0 += bitmap.width as u32;
-            debug_assert!(cursor_offset.0 <= texture_width);
         }
 
-        // filling infos about that character
-        // tex_size and tex_coords are in pixels for the moment ; they will be divided
-        // by the texture dimensions later
-        let left_padding = (*(*face).glyph).bitmap_left;
-
-        Some((character, CharacterInfos {
-            tex_size: (bitmap.width as f32, bitmap.rows as f32),
-            tex_coords: (offset_x_before_copy as f32, cursor_offset.1 as f32),
-            size: (bitmap.width as f32, bitmap.rows as f32),
-            left_padding: left_padding as f32,
-            right_padding: ((*(*face).glyph).advance.x as i32 - bitmap.width * 64 - left_padding * 64) as f32 / 64.0,
-            height_over_line: (*(*face).glyph).bitmap_top as f32,
-        }))
-    }).collect();
-
-    // adding blank lines at the end until the height of the texture is a power of two
-    {
-        let current_height = texture_data.len() as u32 / texture_width;
-        let requested_height = get_nearest_po2(current_height);
-        texture_data.extend(iter::repeat(0.0).take((texture_width * (requested_height - current_height)) as usize));
+        infos.tex_coords = (x as f32, y as f32);
+        infos.page = page;
+        characters_infos.push((character, infos));
     }
 
-    // now our texture is finished
-    // we know its final dimensions, so we can divide all the pixels values into (0,1) range
-    assert!((texture_data.len() as u32 % texture_width) == 0);
-    let texture_height = (texture_data.len() as u32 / texture_width) as f32;
-    let float_texture_width = texture_width as f32;
+    // now our textures are finished
+    // we know their final dimensions, so we can divide all the pixels values into (0,1) range
     for chr in characters_infos.iter_mut() {
-        chr.1.tex_size.0 /= float_texture_width;
+        let (texture_width, texture_height) = pages[chr.1.page];
+        let (texture_width, texture_height) = (texture_width as f32, texture_height as f32);
+        let em_pixels = chr.1.pixel_size as f32;
+        chr.1.tex_size.0 /= texture_width;
         chr.1.tex_size.1 /= texture_height;
-        chr.1.tex_coords.0 /= float_texture_width;
+        chr.1.tex_coords.0 /= texture_width;
         chr.1.tex_coords.1 /= texture_height;
         chr.1.size.0 /= em_pixels;
         chr.1.size.1 /= em_pixels;
@@ -595,11 +1924,21 @@ unsafe fn build_font_image(face: freetype::FT_Face, characters_list: Vec<char>,
     }
 
     // returning
-    (TextureData {
-        data: texture_data,
-        width: texture_width,
-        height: texture_height as u32,
-    }, characters_infos)
+    Ok((pages_data, characters_infos))
+}
+
+// applies the gamma and the contrast of the options to a coverage between 0 and 255
+fn adjust_coverage(coverage: u8, raster: &RasterOptions) -> u8 {
+    if raster.gamma == 1.0 && raster.contrast == 0.0 {
+        return coverage;
+    }
+
+    let coverage = (coverage as f32 / 255.0).powf(1.0 / raster.gamma);
+
+    // moving towards a smoothstep curve, so that empty and fully covered pixels don't change
+    let smooth = coverage * coverage * (3.0 - 2.0 * coverage);
+    let coverage = coverage + (smooth - coverage) * raster.contrast;
+    (coverage.max(0.0).min(1.0) * 255.0).round() as u8
 }
 
 /// Function that will calculate the nearest power of two.
@@ -613,3 +1952,201 @@ fn get_nearest_po2(mut x: u32) -> u32 {
     x = x | (x >> 16);
     x + 1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{adjust_coverage, apply_variations, em_pixel_matrix, nearest_size};
+    use {FontMetrics, FontOptions, NamedInstance, RasterOptions, SubpixelOrder, Variation,
+         VariationAxis};
+    use backend::{GlyphBitmap, GlyphSource, MarkAnchors};
+    #[cfg(feature = "system-fonts")]
+    use backend::FaceInfo;
+
+    #[test]
+    fn nearest_size_is_larger() {
+        let sizes = [12, 16, 32];
+        assert_eq!(nearest_size(&sizes, 8.0), 12);
+        assert_eq!(nearest_size(&sizes, 12.0), 12);
+        assert_eq!(nearest_size(&sizes, 12.5), 16);
+        assert_eq!(nearest_size(&sizes, 31.9999), 32);
+
+        // the glyphs are scaled up when no size is large enough
+        assert_eq!(nearest_size(&sizes, 48.0), 32);
+        assert_eq!(nearest_size(&[24], 12.0), 24);
+    }
+
+    #[test]
+    fn coverage_unchanged_by_default() {
+        let raster = RasterOptions::default();
+        for coverage in 0 ..= 255 {
+            assert_eq!(adjust_coverage(coverage, &raster), coverage);
+        }
+    }
+
+    #[test]
+    fn coverage_gamma() {
+        let darker = RasterOptions { gamma: 2.0, .. RasterOptions::default() };
+        let lighter = RasterOptions { gamma: 0.5, .. RasterOptions::default() };
+
+        // sqrt(128 / 255) * 255 and (128 / 255)^2 * 255
+        assert_eq!(adjust_coverage(128, &darker), 181);
+        assert_eq!(adjust_coverage(128, &lighter), 64);
+
+        // empty and fully covered pixels don't change
+        for raster in &[darker, lighter] {
+            assert_eq!(adjust_coverage(0, raster), 0);
+            assert_eq!(adjust_coverage(255, raster), 255);
+        }
+    }
+
+    #[test]
+    fn coverage_contrast() {
+        let more = RasterOptions { contrast: 1.0, .. RasterOptions::default() };
+        let less = RasterOptions { contrast: -1.0, .. RasterOptions::default() };
+
+        // the smoothstep curve pushes the coverage away from the middle
+        assert_eq!(adjust_coverage(64, &more), 40);
+        assert_eq!(adjust_coverage(192, &more), 216);
+        assert_eq!(adjust_coverage(64, &less), 88);
+        assert_eq!(adjust_coverage(192, &less), 168);
+
+        for raster in &[more, less] {
+            assert_eq!(adjust_coverage(0, raster), 0);
+            assert_eq!(adjust_coverage(128, raster), 128);
+            assert_eq!(adjust_coverage(255, raster), 255);
+        }
+    }
+
+    // returns the pixel of a target of the given dimensions, counted from its top-left corner,
+    // where a point given in EM ends up
+    fn to_pixels(matrix: &[[f32; 4]; 4], dimensions: (u32, u32), point: (f32, f32)) -> (f32, f32) {
+        let x = matrix[0][0] * point.0 + matrix[1][0] * point.1 + matrix[3][0];
+        let y = matrix[0][1] * point.0 + matrix[1][1] * point.1 + matrix[3][1];
+        ((x + 1.0) / 2.0 * dimensions.0 as f32, (1.0 - y) / 2.0 * dimensions.1 as f32)
+    }
+
+    #[test]
+    fn em_pixel_matrix_positions() {
+        // the position is rounded to (10, 21) so that the glyphs stay on the pixels
+        let matrix = em_pixel_matrix(10.0, (100, 50), (10.4, 20.6));
+        assert_eq!(matrix[2], [0.0, 0.0, 1.0, 0.0]);
+
+        for &(point, expected) in &[((0.0, 0.0), (10.0, 21.0)), ((1.0, 0.0), (20.0, 21.0)),
+                                    ((0.0, 1.0), (10.0, 11.0)), ((2.5, -0.5), (35.0, 26.0))]
+        {
+            let (x, y) = to_pixels(&matrix, (100, 50), point);
+            assert!((x - expected.0).abs() < 1e-4 && (y - expected.1).abs() < 1e-4,
+                    "{:?} is at {:?} instead of {:?}", point, (x, y), expected);
+        }
+    }
+
+    /// A face that only has a `wght` axis between 100 and 900 and a `wdth` axis between 50 and
+    /// 100, and remembers the coordinates it was given.
+    struct VariableFace {
+        instances: Vec<NamedInstance>,
+        coordinates: Option<Vec<f32>>,
+    }
+
+    impl VariableFace {
+        fn new() -> VariableFace {
+            let instance = |name: &str, coordinates: Vec<f32>| NamedInstance {
+                name: name.to_owned(),
+                coordinates: coordinates,
+            };
+
+            VariableFace {
+                instances: vec![instance("Bold", vec![700.0, 100.0]),
+                                instance("Broken", vec![700.0])],
+                coordinates: None,
+            }
+        }
+    }
+
+    impl GlyphSource for VariableFace {
+        fn set_pixel_size(&mut self, _: u32) -> Result<(), ()> { unreachable!() }
+        fn metrics(&self) -> FontMetrics { unreachable!() }
+        fn characters(&self) -> Vec<(char, u32)> { unreachable!() }
+        fn set_raster_options(&mut self, _: &RasterOptions, _: Option<SubpixelOrder>) {
+            unreachable!()
+        }
+        fn rasterize(&mut self, _: u32, _: f32) -> Option<GlyphBitmap> { unreachable!() }
+
+        fn variation_axes(&self) -> Vec<VariationAxis> {
+            let axis = |tag: &[u8; 4], min, default, max| VariationAxis {
+                tag: *tag,
+                name: String::new(),
+                min: min,
+                default: default,
+                max: max,
+            };
+
+            vec![axis(b"wght", 100.0, 400.0, 900.0), axis(b"wdth", 50.0, 100.0, 100.0)]
+        }
+
+        fn named_instances(&self) -> Vec<NamedInstance> {
+            self.instances.clone()
+        }
+
+        fn set_variations(&mut self, coordinates: &[f32]) -> Result<(), ()> {
+            self.coordinates = Some(coordinates.to_vec());
+            Ok(())
+        }
+
+        fn mark_anchors(&self, _: &[u32]) -> MarkAnchors { unreachable!() }
+        fn substitutions(&self, _: &[u8; 4], _: &[u32]) -> Vec<(u32, u32)> { unreachable!() }
+        fn vertical_metrics(&self, _: u32) -> Option<(f32, f32)> { unreachable!() }
+        #[cfg(feature = "system-fonts")]
+        fn faces_count(&self) -> u32 { unreachable!() }
+        #[cfg(feature = "system-fonts")]
+        fn info(&self) -> FaceInfo { unreachable!() }
+    }
+
+    fn variation(tag: &[u8; 4], value: f32) -> Variation {
+        Variation { tag: *tag, value: value }
+    }
+
+    #[test]
+    fn variations_default() {
+        let mut face = VariableFace::new();
+        let applied = apply_variations(&mut face, &Default::default()).unwrap();
+        assert_eq!(applied, vec![variation(b"wght", 400.0), variation(b"wdth", 100.0)]);
+        assert_eq!(face.coordinates, Some(vec![400.0, 100.0]));
+    }
+
+    #[test]
+    fn variations_clamped() {
+        // the values are clamped to the range of their axis, and unknown axes are ignored
+        let options = FontOptions {
+            variations: vec![variation(b"wdth", 20.0), variation(b"slnt", -10.0),
+                             variation(b"wght", 1000.0)],
+            .. Default::default()
+        };
+
+        let mut face = VariableFace::new();
+        let applied = apply_variations(&mut face, &options).unwrap();
+        assert_eq!(applied, vec![variation(b"wght", 900.0), variation(b"wdth", 50.0)]);
+        assert_eq!(face.coordinates, Some(vec![900.0, 50.0]));
+    }
+
+    #[test]
+    fn variations_named_instance() {
+        // the variations are applied on top of the instance
+        let options = FontOptions {
+            named_instance: Some(0),
+            variations: vec![variation(b"wdth", 75.0)],
+            .. Default::default()
+        };
+
+        let mut face = VariableFace::new();
+        let applied = apply_variations(&mut face, &options).unwrap();
+        assert_eq!(applied, vec![variation(b"wght", 700.0), variation(b"wdth", 75.0)]);
+
+        // the second instance doesn't have a coordinate for each axis, and there is no third one
+        for &index in &[1, 2] {
+            let options = FontOptions { named_instance: Some(index), .. Default::default() };
+            let mut face = VariableFace::new();
+            assert!(apply_variations(&mut face, &options).is_err());
+            assert_eq!(face.coordinates, None);
+        }
+    }
+}
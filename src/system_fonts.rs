@@ -0,0 +1,407 @@
+/*!
+Discovery of the fonts installed on the system.
+
+A `FontDatabase` indexes the family, style and weight of every font file found in a list of
+directories, and can then resolve queries like `"sans-serif, bold"` to one of them.
+
+```no_run
+# extern crate glium;
+# extern crate glium_text;
+# fn main() {
+# let display: glium::Display = unsafe { std::mem::uninitialized() };
+let database = glium_text::system_fonts::FontDatabase::system();
+let entry = database.query("DejaVu Sans, sans-serif, bold").unwrap();
+let font = glium_text::FontTexture::from_bytes(&display, &entry.data().unwrap(), 24,
+                                               &entry.options()).unwrap();
+# }
+```
+
+On Linux and other Unix systems, the directories are read from the fontconfig configuration.
+The standard font directories are used on Windows and OS X.
+
+*/
+
+use backend::{self, GlyphSource};
+use FontOptions;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A face found in a font file.
+#[derive(Clone, Debug)]
+pub struct FontEntry {
+    /// Path of the font file.
+    pub path: PathBuf,
+
+    /// Index of the face in the file, when the file is a font collection.
+    pub face_index: u32,
+
+    /// Name of the family, for example `DejaVu Sans`.
+    pub family: String,
+
+    /// Name of the style, for example `Bold Oblique`.
+    pub style: String,
+
+    /// Weight between 1 and 1000. Regular fonts are 400 and bold fonts are 700.
+    pub weight: u16,
+
+    /// Width between 1 and 9. Condensed fonts are below 5 and expanded fonts above 5.
+    pub width: u16,
+
+    /// True if the face is italic or oblique.
+    pub italic: bool,
+}
+
+impl FontEntry {
+    /// Reads the content of the font file.
+    pub fn data(&self) -> Result<Vec<u8>, ()> {
+        fs::read(&self.path).map_err(|_| ())
+    }
+
+    /// Returns the options that select this face when building a `FontTexture`.
+    pub fn options(&self) -> FontOptions {
+        FontOptions {
+            face_index: self.face_index,
+            .. Default::default()
+        }
+    }
+}
+
+/// List of the fonts found in some directories.
+#[derive(Clone, Debug, Default)]
+pub struct FontDatabase {
+    fonts: Vec<FontEntry>,
+}
+
+impl FontDatabase {
+    /// Builds an empty database.
+    pub fn new() -> FontDatabase {
+        FontDatabase {
+            fonts: Vec::new(),
+        }
+    }
+
+    /// Builds a database containing the fonts installed on the system.
+    pub fn system() -> FontDatabase {
+        let mut database = FontDatabase::new();
+        for directory in system_directories() {
+            database.add_directory(directory);
+        }
+        database
+    }
+
+    /// Adds all the fonts of a directory and of its subdirectories.
+    ///
+    /// Files that can't be read or that aren't fonts are ignored.
+    pub fn add_directory<P>(&mut self, directory: P) where P: AsRef<Path> {
+        let mut visited = HashSet::new();
+        self.scan_directory(directory.as_ref(), &mut visited);
+    }
+
+    /// Adds all the faces of a font file.
+    ///
+    /// Returns an error if the file can't be read or isn't a font.
+    pub fn add_file<P>(&mut self, path: P) -> Result<(), ()> where P: AsRef<Path> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|_| ())?;
+
+        let faces_count = backend::Face::new(&data, 0)?.faces_count();
+        for face_index in 0 .. faces_count {
+            let info = match backend::Face::new(&data, face_index) {
+                Ok(face) => face.info(),
+                Err(_) => continue
+            };
+
+            self.fonts.push(FontEntry {
+                path: path.to_path_buf(),
+                face_index: face_index,
+                family: info.family,
+                style: info.style,
+                weight: info.weight,
+                width: info.width,
+                italic: info.italic,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the list of all the faces in the database.
+    pub fn fonts(&self) -> &[FontEntry] {
+        &self.fonts
+    }
+
+    /// Finds the face that best matches a query.
+    ///
+    /// The query is a comma-separated list of family names and style keywords, for example
+    /// `"Helvetica, Arial, sans-serif, bold italic"`. The families are tried in order, and the
+    /// generic families `serif`, `sans-serif`, `monospace`, `cursive` and `fantasy` are
+    /// replaced with common fonts of that kind. The recognized style keywords are the CSS
+    /// weight names (`thin`, `light`, `bold`, `black`...), numeric weights, `italic` and
+    /// `oblique`.
+    ///
+    /// If none of the families is installed, the best match among `sans-serif` fonts is
+    /// returned, and then the best match among all the fonts. Returns `None` only if the
+    /// database is empty.
+    pub fn query(&self, query: &str) -> Option<&FontEntry> {
+        let mut families = Vec::new();
+        let mut weight = 400;
+        let mut italic = false;
+
+        for token in query.split(',') {
+            let token = token.trim().trim_matches(|c| c == '"' || c == '\'');
+            if token.is_empty() {
+                continue;
+            }
+
+            let mut is_style = true;
+            let mut token_weight = None;
+            let mut token_italic = false;
+            for word in token.split_whitespace() {
+                match parse_style_keyword(word) {
+                    Some(Style::Weight(w)) => token_weight = Some(w),
+                    Some(Style::Italic) => token_italic = true,
+                    Some(Style::Regular) => (),
+                    None => { is_style = false; break; }
+                }
+            }
+
+            if is_style {
+                weight = token_weight.unwrap_or(weight);
+                italic |= token_italic;
+            } else {
+                families.push(token);
+            }
+        }
+
+        families.push("sans-serif");
+
+        for family in families {
+            let generic = generic_family(family);
+            let names = generic.unwrap_or(&[]).iter().cloned()
+                               .chain(if generic.is_none() { Some(family) } else { None });
+
+            for name in names {
+                let candidates = self.fonts.iter().filter(|f| f.family.eq_ignore_ascii_case(name));
+                if let Some(font) = best_match(candidates, weight, italic) {
+                    return Some(font);
+                }
+            }
+        }
+
+        best_match(self.fonts.iter(), weight, italic)
+    }
+
+    fn scan_directory(&mut self, directory: &Path, visited: &mut HashSet<PathBuf>) {
+        // protecting against symbolic links that loop
+        match fs::canonicalize(directory) {
+            Ok(path) => if !visited.insert(path) { return; },
+            Err(_) => return
+        };
+
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return
+        };
+
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        paths.sort();
+
+        for path in paths {
+            if path.is_dir() {
+                self.scan_directory(&path, visited);
+                continue;
+            }
+
+            let is_font = path.extension().and_then(|e| e.to_str()).map(|e| {
+                let e = e.to_ascii_lowercase();
+                e == "ttf" || e == "otf" || e == "ttc" || e == "otc"
+            }).unwrap_or(false);
+
+            if is_font {
+                let _ = self.add_file(&path);
+            }
+        }
+    }
+}
+
+enum Style {
+    Weight(u16),
+    Italic,
+    Regular,
+}
+
+fn parse_style_keyword(word: &str) -> Option<Style> {
+    if let Ok(weight) = word.parse::<u16>() {
+        if (1 ..= 1000).contains(&weight) {
+            return Some(Style::Weight(weight));
+        }
+    }
+
+    let weight = match &word.to_ascii_lowercase()[..] {
+        "thin" | "hairline" => 100,
+        "extralight" | "ultralight" => 200,
+        "light" => 300,
+        "normal" | "regular" | "book" | "roman" => return Some(Style::Regular),
+        "medium" => 500,
+        "semibold" | "demibold" => 600,
+        "bold" => 700,
+        "extrabold" | "ultrabold" => 800,
+        "black" | "heavy" => 900,
+        "italic" | "oblique" => return Some(Style::Italic),
+        _ => return None
+    };
+
+    Some(Style::Weight(weight))
+}
+
+fn generic_family(family: &str) -> Option<&'static [&'static str]> {
+    static SANS_SERIF: &'static [&'static str] = &["DejaVu Sans", "Liberation Sans", "Noto Sans",
+        "Arial", "Helvetica", "Segoe UI", "Verdana", "FreeSans"];
+    static SERIF: &'static [&'static str] = &["DejaVu Serif", "Liberation Serif", "Noto Serif",
+        "Times New Roman", "Times", "Georgia", "FreeSerif"];
+    static MONOSPACE: &'static [&'static str] = &["DejaVu Sans Mono", "Liberation Mono",
+        "Noto Sans Mono", "Consolas", "Menlo", "Monaco", "Courier New", "FreeMono"];
+    static CURSIVE: &'static [&'static str] = &["Comic Sans MS", "Apple Chancery", "URW Chancery L"];
+    static FANTASY: &'static [&'static str] = &["Impact", "Papyrus", "Copperplate"];
+
+    match &family.to_ascii_lowercase()[..] {
+        "sans-serif" | "sans" | "system-ui" => Some(SANS_SERIF),
+        "serif" => Some(SERIF),
+        "monospace" | "mono" => Some(MONOSPACE),
+        "cursive" => Some(CURSIVE),
+        "fantasy" => Some(FANTASY),
+        _ => None
+    }
+}
+
+// picks the font with the right slant, then the normal width, then the closest weight
+fn best_match<'a, I>(fonts: I, weight: u16, italic: bool) -> Option<&'a FontEntry>
+                     where I: Iterator<Item = &'a FontEntry>
+{
+    fonts.min_by_key(|f| {
+        let weight_distance = (f.weight as i32 - weight as i32).abs();
+        let width_distance = (f.width as i32 - 5).abs();
+        (f.italic != italic, width_distance, weight_distance)
+    })
+}
+
+fn home_directory() -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+#[cfg(target_os = "windows")]
+fn system_directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+
+    let windows = env::var_os("WINDIR").or_else(|| env::var_os("SystemRoot"))
+                                       .map(PathBuf::from)
+                                       .unwrap_or_else(|| PathBuf::from("C:\\Windows"));
+    directories.push(windows.join("Fonts"));
+
+    if let Some(local) = env::var_os("LOCALAPPDATA") {
+        directories.push(PathBuf::from(local).join("Microsoft\\Windows\\Fonts"));
+    }
+
+    directories
+}
+
+#[cfg(target_os = "macos")]
+fn system_directories() -> Vec<PathBuf> {
+    let mut directories = vec![
+        PathBuf::from("/System/Library/Fonts"),
+        PathBuf::from("/Library/Fonts"),
+        PathBuf::from("/Network/Library/Fonts"),
+    ];
+
+    if let Some(home) = home_directory() {
+        directories.push(home.join("Library/Fonts"));
+    }
+
+    directories
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn system_directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+
+    // the directories listed by the fontconfig configuration
+    let mut configs = vec![PathBuf::from("/etc/fonts/fonts.conf")];
+    if let Ok(entries) = fs::read_dir("/etc/fonts/conf.d") {
+        let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        entries.sort();
+        configs.extend(entries);
+    }
+
+    for config in configs {
+        if let Ok(config) = fs::read(&config) {
+            directories.extend(fontconfig_directories(&String::from_utf8_lossy(&config)));
+        }
+    }
+
+    // the default directories, in case there's no fontconfig configuration
+    directories.push(PathBuf::from("/usr/share/fonts"));
+    directories.push(PathBuf::from("/usr/local/share/fonts"));
+    directories.push(xdg_data_home().join("fonts"));
+    if let Some(home) = home_directory() {
+        directories.push(home.join(".fonts"));
+    }
+
+    let mut unique = HashSet::new();
+    directories.retain(|d| unique.insert(d.clone()));
+    directories
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn xdg_data_home() -> PathBuf {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => home_directory().unwrap_or_else(PathBuf::new).join(".local/share"),
+    }
+}
+
+// extracts the content of the `<dir>` elements of a fontconfig configuration file
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn fontconfig_directories(config: &str) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    let mut rest = config;
+
+    while let Some(start) = rest.find("<dir") {
+        rest = &rest[start + 4 ..];
+
+        // skipping elements like `<dirs>`
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => break
+        };
+        let attributes = &rest[.. tag_end];
+        if !attributes.is_empty() && !attributes.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        rest = &rest[tag_end + 1 ..];
+        let content_end = match rest.find("</dir>") {
+            Some(end) => end,
+            None => break
+        };
+        let content = rest[.. content_end].trim();
+        rest = &rest[content_end ..];
+
+        let path = if attributes.contains("prefix=\"xdg\"") {
+            xdg_data_home().join(content)
+        } else if content.starts_with("~/") {
+            match home_directory() {
+                Some(home) => home.join(&content[2 ..]),
+                None => continue
+            }
+        } else {
+            PathBuf::from(content)
+        };
+
+        result.push(path);
+    }
+
+    result
+}
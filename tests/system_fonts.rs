@@ -0,0 +1,47 @@
+extern crate glium_text;
+
+use glium_text::system_fonts::FontDatabase;
+use std::path::Path;
+
+// the `examples` directory contains a single font, which is used as a fixture
+fn fixture() -> FontDatabase {
+    let mut database = FontDatabase::new();
+    database.add_directory(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples"));
+    database
+}
+
+#[test]
+fn scan_directory() {
+    let database = fixture();
+    assert_eq!(database.fonts().len(), 1);
+
+    let font = &database.fonts()[0];
+    assert_eq!(font.family, "Hans Kendrick 3");
+    assert_eq!(font.face_index, 0);
+    assert_eq!(font.weight, 400);
+    assert!(!font.italic);
+    assert!(font.path.ends_with("font.ttf"));
+}
+
+#[test]
+fn query_by_family() {
+    let database = fixture();
+    let font = database.query("hans kendrick 3, bold").unwrap();
+    assert_eq!(font.family, "Hans Kendrick 3");
+    assert!(font.data().unwrap().len() > 0);
+}
+
+#[test]
+fn query_fallback() {
+    let database = fixture();
+    assert!(database.query("This Font Doesn't Exist, serif, italic").is_some());
+    assert!(FontDatabase::new().query("sans-serif").is_none());
+}
+
+#[test]
+fn non_font_files_are_ignored() {
+    let mut database = FontDatabase::new();
+    database.add_directory(Path::new(env!("CARGO_MANIFEST_DIR")).join("src"));
+    assert!(database.fonts().is_empty());
+    assert!(database.add_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")).is_err());
+}